@@ -0,0 +1,105 @@
+use std::{collections::HashMap, io::Cursor};
+
+use clap::Args;
+use hound::WavReader;
+use rustpotter::{WakewordLoad, WakewordRef, WakewordRefBuildFromBuffers, WakewordSave};
+
+use super::{audio::read_wav_samples, calibrate::get_scores};
+
+/// Standard deviations under the group mean similarity that flag a sample as outlier.
+const OUTLIER_DEVIATIONS: f32 = 1.5;
+/// Min difference with the group mean similarity that flags a sample as outlier.
+const MIN_OUTLIER_GAP: f32 = 0.02;
+
+#[derive(Args, Debug)]
+/// Options to compare the samples between them.
+pub(crate) struct AnalyzeSettings {
+    #[clap(long)]
+    /// Print the similarity between each pair of samples and flag the ones less similar to the others.
+    pub analyze: bool,
+    #[clap(long, requires = "analyze")]
+    /// Build the reference without the samples flagged as outliers.
+    pub drop_outliers: bool,
+}
+
+/// Scores each sample against a reference of each other one, prints the similarity matrix and returns the outlier names.
+pub(crate) fn analyze(
+    samples_buffers: &[(String, Vec<u8>)],
+    mfcc_size: u16,
+) -> Result<Vec<String>, String> {
+    if samples_buffers.len() < 3 {
+        return Err("At least three samples are required to analyze".to_string());
+    }
+    let mut samples_list = Vec::with_capacity(samples_buffers.len());
+    let mut wakeword_buffers = Vec::with_capacity(samples_buffers.len());
+    for (name, buffer) in samples_buffers {
+        let mut wav_reader = WavReader::new(Cursor::new(buffer)).map_err(|err| err.to_string())?;
+        samples_list.push(read_wav_samples(&mut wav_reader)?);
+        let wakeword = WakewordRef::new_from_sample_buffers(
+            name.clone(),
+            None,
+            None,
+            HashMap::from([(name.clone(), buffer.clone())]),
+            mfcc_size,
+        )?;
+        wakeword_buffers.push(wakeword.save_to_buffer()?);
+    }
+    let samples_count = samples_buffers.len();
+    let mut similarities = vec![vec![1.; samples_count]; samples_count];
+    for (index, (name, _)) in samples_buffers.iter().enumerate() {
+        let (spec, samples) = &samples_list[index];
+        for (other_index, wakeword_buffer) in wakeword_buffers.iter().enumerate() {
+            if other_index != index {
+                let wakeword = WakewordRef::load_from_buffer(wakeword_buffer)?;
+                similarities[index][other_index] = get_scores(name, wakeword, spec, samples)?.score;
+            }
+        }
+    }
+    // the comparison is not symmetric, so both directions are averaged
+    let mean_similarities = (0..samples_count)
+        .map(|index| {
+            (0..samples_count)
+                .filter(|other_index| *other_index != index)
+                .map(|other_index| {
+                    (similarities[index][other_index] + similarities[other_index][index]) / 2.
+                })
+                .sum::<f32>()
+                / (samples_count - 1) as f32
+        })
+        .collect::<Vec<_>>();
+    let group_mean = mean_similarities.iter().sum::<f32>() / samples_count as f32;
+    let group_deviation = (mean_similarities
+        .iter()
+        .map(|similarity| (similarity - group_mean).powi(2))
+        .sum::<f32>()
+        / samples_count as f32)
+        .sqrt();
+    let outlier_limit =
+        (group_mean - OUTLIER_DEVIATIONS * group_deviation).min(group_mean - MIN_OUTLIER_GAP);
+    println!("Similarity matrix:");
+    print!("{:>4}", "");
+    for index in 0..samples_count {
+        print!(" {:>6}", index + 1);
+    }
+    println!("   mean");
+    let mut outliers = Vec::new();
+    for (index, (name, _)) in samples_buffers.iter().enumerate() {
+        print!("{:>4}", index + 1);
+        for similarity in &similarities[index] {
+            print!(" {:>6.3}", similarity);
+        }
+        print!(" {:>6.3} {}", mean_similarities[index], name);
+        if mean_similarities[index] < outlier_limit {
+            print!(" (outlier)");
+            outliers.push(name.clone());
+        }
+        println!();
+    }
+    println!(
+        "Group mean similarity {:.3}, deviation {:.3}, {} outliers.",
+        group_mean,
+        group_deviation,
+        outliers.len()
+    );
+    Ok(outliers)
+}
@@ -0,0 +1,137 @@
+use std::io::Read;
+
+use hound::{SampleFormat, WavReader, WavSpec};
+use rand::{rngs::StdRng, Rng};
+use rustpotter::Sample;
+
+/// Absolute sample value considered clipped.
+pub(crate) const CLIPPING_LEVEL: f32 = 0.999;
+
+/// Mono noise record, as float samples.
+pub(crate) struct Noise {
+    sample_rate: usize,
+    samples: Vec<f32>,
+}
+
+/// Returns the sorted paths of the wav files in the directory.
+pub(crate) fn get_wav_paths(dir: &str) -> Result<Vec<String>, String> {
+    let mut paths = std::fs::read_dir(dir)
+        .map_err(|err| "Unable to read directory ".to_owned() + dir + ": " + &err.to_string())?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "wav"))
+        .map(|path| path.to_string_lossy().to_string())
+        .collect::<Vec<_>>();
+    paths.sort();
+    Ok(paths)
+}
+/// Reads the wav record as float samples, returns them with the spec that describes them.
+pub(crate) fn read_samples(path: &str) -> Result<(WavSpec, Vec<f32>), String> {
+    let mut wav_reader = WavReader::open(path)
+        .map_err(|err| "Unable to open file ".to_owned() + path + ": " + &err.to_string())?;
+    read_wav_samples(&mut wav_reader)
+}
+/// Reads the wav samples as float ones, returns them with the spec that describes them.
+pub(crate) fn read_wav_samples<R: Read>(
+    wav_reader: &mut WavReader<R>,
+) -> Result<(WavSpec, Vec<f32>), String> {
+    let wav_specs = wav_reader.spec();
+    let samples = match (wav_specs.sample_format, wav_specs.bits_per_sample) {
+        (SampleFormat::Int, 8) => collect_samples::<i8, R>(wav_reader),
+        (SampleFormat::Int, 16) => collect_samples::<i16, R>(wav_reader),
+        (SampleFormat::Int, 32) => collect_samples::<i32, R>(wav_reader),
+        (SampleFormat::Float, 32) => collect_samples::<f32, R>(wav_reader),
+        _ => Err("Unsupported wav format".to_string()),
+    }?;
+    Ok((
+        WavSpec {
+            sample_format: SampleFormat::Float,
+            bits_per_sample: 32,
+            ..wav_specs
+        },
+        samples,
+    ))
+}
+fn collect_samples<T: hound::Sample + Sample, R: Read>(
+    wav_reader: &mut WavReader<R>,
+) -> Result<Vec<f32>, String> {
+    wav_reader
+        .samples::<T>()
+        .map(|sample| sample.map(T::into_f32).map_err(|err| err.to_string()))
+        .collect()
+}
+/// Reads the first channel of the wav records in the directory.
+pub(crate) fn read_noises(noise_dir: &str) -> Result<Vec<Noise>, String> {
+    let mut noises = Vec::new();
+    for path in get_wav_paths(noise_dir)? {
+        let (wav_specs, samples) = read_samples(&path)?;
+        let samples = samples
+            .chunks_exact(wav_specs.channels as usize)
+            .map(|frame| frame[0])
+            .collect::<Vec<_>>();
+        if !samples.is_empty() {
+            noises.push(Noise {
+                sample_rate: wav_specs.sample_rate as usize,
+                samples,
+            });
+        }
+    }
+    if noises.is_empty() {
+        return Err("No wav records found in ".to_owned() + noise_dir);
+    }
+    Ok(noises)
+}
+/// Takes a segment of the requested length from a random offset of the noise,
+/// linearly resampled to the sample rate and looped when the noise is shorter.
+pub(crate) fn get_noise_segment(
+    noise: &Noise,
+    sample_rate: usize,
+    len: usize,
+    rng: &mut StdRng,
+) -> Vec<f32> {
+    let step = noise.sample_rate as f64 / sample_rate as f64;
+    let noise_len = noise.samples.len();
+    let offset = rng.gen_range(0..noise_len) as f64;
+    (0..len)
+        .map(|index| {
+            let position = offset + index as f64 * step;
+            let fraction = position.fract() as f32;
+            let sample = noise.samples[position as usize % noise_len];
+            let next_sample = noise.samples[(position as usize + 1) % noise_len];
+            sample + (next_sample - sample) * fraction
+        })
+        .collect()
+}
+/// Adds the noise to every channel of the samples, scaled to get the signal to noise ratio.
+pub(crate) fn mix_noise(samples: &[f32], channels: u16, noise: &[f32], snr_db: f32) -> Vec<f32> {
+    let signal_rms = get_rms(samples);
+    let noise_rms = get_rms(noise);
+    let noise_gain = if noise_rms == 0. {
+        0.
+    } else {
+        signal_rms / (noise_rms * 10_f32.powf(snr_db / 20.))
+    };
+    samples
+        .chunks_exact(channels as usize)
+        .zip(noise)
+        .flat_map(|(frame, noise_sample)| {
+            frame
+                .iter()
+                .map(move |sample| (sample + noise_sample * noise_gain).clamp(-1., 1.))
+        })
+        .collect()
+}
+/// Returns the rms level of the samples.
+pub(crate) fn get_rms(samples: &[f32]) -> f32 {
+    get_rms_from_square_sum(
+        samples.iter().map(|sample| (*sample as f64).powi(2)).sum(),
+        samples.len(),
+    )
+}
+/// Returns the rms level of a number of samples from the sum of their squares.
+pub(crate) fn get_rms_from_square_sum(square_sum: f64, samples: usize) -> f32 {
+    (square_sum / samples.max(1) as f64).sqrt() as f32
+}
+/// Converts a sample level to dBFS.
+pub(crate) fn to_dbfs(level: f32) -> f32 {
+    20. * level.max(1e-6).log10()
+}
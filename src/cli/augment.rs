@@ -0,0 +1,280 @@
+use std::{collections::HashMap, io::Cursor};
+
+use clap::Args;
+use hound::{WavReader, WavSpec, WavWriter};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::audio::{get_noise_segment, mix_noise, read_noises, read_wav_samples, Noise};
+
+#[derive(Args, Debug)]
+/// Options to train with randomly modified copies of the training records.
+pub(crate) struct AugmentSettings {
+    #[clap(long)]
+    /// Add randomly modified copies of the training records, generated again every few epochs.
+    /// The test records are never augmented.
+    pub augment: bool,
+    #[clap(long, default_value_t = 1, requires = "augment")]
+    /// Number of augmented copies of each training record.
+    augment_copies: usize,
+    #[clap(long, default_value_t = 1, requires = "augment")]
+    /// Number of epochs trained with each set of augmented copies.
+    /// The records features are extracted again for each set, so a low value slows down the training.
+    pub augment_epochs: usize,
+    #[clap(long, default_value_t = 0.5, requires = "augment")]
+    /// Probability of shifting the audio in time.
+    shift_probability: f64,
+    #[clap(long, default_value_t = 200, requires = "augment")]
+    /// Max time shift in milliseconds, in both directions, the record duration is kept.
+    max_shift_ms: usize,
+    #[clap(long, default_value_t = 0.5, requires = "augment")]
+    /// Probability of scaling the audio gain.
+    gain_probability: f64,
+    #[clap(long, default_value_t = -6., allow_hyphen_values = true, requires = "augment")]
+    /// Min gain applied, in decibels.
+    min_gain_db: f32,
+    #[clap(
+        long,
+        default_value_t = 6.,
+        allow_hyphen_values = true,
+        requires = "augment"
+    )]
+    /// Max gain applied, in decibels.
+    max_gain_db: f32,
+    #[clap(long, requires = "augment")]
+    /// Directory containing wav records of noise to mix into the training records.
+    noise_dir: Option<String>,
+    #[clap(long, default_value_t = 0.5, requires = "noise_dir")]
+    /// Probability of mixing noise into the audio.
+    noise_probability: f64,
+    #[clap(
+        long,
+        default_value_t = 5.,
+        allow_hyphen_values = true,
+        requires = "noise_dir"
+    )]
+    /// Min signal to noise ratio, in decibels.
+    min_snr_db: f32,
+    #[clap(
+        long,
+        default_value_t = 20.,
+        allow_hyphen_values = true,
+        requires = "noise_dir"
+    )]
+    /// Max signal to noise ratio, in decibels.
+    max_snr_db: f32,
+    #[clap(long, default_value_t = 0.5, requires = "augment")]
+    /// Probability of changing the audio speed, the record duration is kept.
+    speed_probability: f64,
+    #[clap(long, default_value_t = 0.9, requires = "augment")]
+    /// Min speed factor.
+    min_speed: f32,
+    #[clap(long, default_value_t = 1.1, requires = "augment")]
+    /// Max speed factor.
+    max_speed: f32,
+}
+
+/// Generates the augmented copies of the training records.
+pub(crate) struct Augmenter<'a> {
+    settings: &'a AugmentSettings,
+    noises: Vec<Noise>,
+    rng: StdRng,
+}
+impl<'a> Augmenter<'a> {
+    pub(crate) fn new(settings: &'a AugmentSettings, seed: u64) -> Result<Augmenter<'a>, String> {
+        let probabilities = [
+            settings.shift_probability,
+            settings.gain_probability,
+            settings.noise_probability,
+            settings.speed_probability,
+        ];
+        if probabilities
+            .iter()
+            .any(|probability| !(0. ..=1.).contains(probability))
+        {
+            return Err("Augmentation probabilities should be in the range 0 - 1".to_string());
+        }
+        if settings.min_gain_db > settings.max_gain_db
+            || settings.min_snr_db > settings.max_snr_db
+            || settings.min_speed > settings.max_speed
+        {
+            return Err("Augmentation min values should not exceed the max ones".to_string());
+        }
+        if settings.min_speed <= 0. {
+            return Err("Augmentation speed should be over zero".to_string());
+        }
+        let noises = match settings.noise_dir.as_ref() {
+            Some(noise_dir) => read_noises(noise_dir)?,
+            None => Vec::new(),
+        };
+        Ok(Augmenter {
+            settings,
+            noises,
+            rng: StdRng::seed_from_u64(seed),
+        })
+    }
+    /// Returns the records with their augmented copies, whose names keep the record label.
+    pub(crate) fn augment(
+        &mut self,
+        samples: &HashMap<String, Vec<u8>>,
+    ) -> Result<HashMap<String, Vec<u8>>, String> {
+        // sorted so the seed produces the same copies on every run
+        let mut names = samples.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut augmented_samples = samples.clone();
+        for name in names {
+            let mut wav_reader =
+                WavReader::new(Cursor::new(&samples[name])).map_err(|err| err.to_string())?;
+            let (spec, float_samples) = read_wav_samples(&mut wav_reader)?;
+            for index in 0..self.settings.augment_copies {
+                let augmented = self.augment_sample(&spec, float_samples.clone());
+                augmented_samples.insert(
+                    format!("{}.augmented{}", name, index),
+                    to_wav_buffer(&spec, &augmented)?,
+                );
+            }
+        }
+        Ok(augmented_samples)
+    }
+    fn augment_sample(&mut self, spec: &WavSpec, mut samples: Vec<f32>) -> Vec<f32> {
+        let channels = spec.channels as usize;
+        if self.rng.gen_bool(self.settings.speed_probability) {
+            let speed = self
+                .rng
+                .gen_range(self.settings.min_speed..=self.settings.max_speed);
+            samples = change_speed(&samples, channels, speed);
+        }
+        if self.rng.gen_bool(self.settings.shift_probability) {
+            let max_shift =
+                (self.settings.max_shift_ms * spec.sample_rate as usize / 1000) as isize;
+            let shift = self.rng.gen_range(-max_shift..=max_shift);
+            samples = shift_samples(&samples, channels, shift);
+        }
+        if self.rng.gen_bool(self.settings.gain_probability) {
+            let gain_db = self
+                .rng
+                .gen_range(self.settings.min_gain_db..=self.settings.max_gain_db);
+            let gain = 10_f32.powf(gain_db / 20.);
+            samples
+                .iter_mut()
+                .for_each(|sample| *sample = (*sample * gain).clamp(-1., 1.));
+        }
+        // mixed last, so the ratio is relative to the modified audio
+        if !self.noises.is_empty() && self.rng.gen_bool(self.settings.noise_probability) {
+            let snr_db = self
+                .rng
+                .gen_range(self.settings.min_snr_db..=self.settings.max_snr_db);
+            let noise = &self.noises[self.rng.gen_range(0..self.noises.len())];
+            let noise_segment = get_noise_segment(
+                noise,
+                spec.sample_rate as usize,
+                samples.len() / channels,
+                &mut self.rng,
+            );
+            samples = mix_noise(&samples, spec.channels, &noise_segment, snr_db);
+        }
+        samples
+    }
+}
+/// Resamples the audio by the speed factor, padding with silence or truncating to keep its duration.
+fn change_speed(samples: &[f32], channels: usize, speed: f32) -> Vec<f32> {
+    let frames = samples.len() / channels;
+    let mut output = vec![0.; frames * channels];
+    for frame in 0..frames {
+        let position = frame as f32 * speed;
+        let index = position as usize;
+        if index >= frames {
+            break;
+        }
+        let fraction = position.fract();
+        // the last frame has no next one to interpolate with
+        let next_index = (index + 1).min(frames - 1);
+        for channel in 0..channels {
+            let sample = samples[index * channels + channel];
+            let next_sample = samples[next_index * channels + channel];
+            output[frame * channels + channel] = sample + (next_sample - sample) * fraction;
+        }
+    }
+    output
+}
+/// Moves the audio by the number of frames, filling with silence, a positive shift delays it.
+fn shift_samples(samples: &[f32], channels: usize, shift: isize) -> Vec<f32> {
+    let shift_len = shift.unsigned_abs() * channels;
+    if shift_len >= samples.len() {
+        return vec![0.; samples.len()];
+    }
+    if shift >= 0 {
+        [
+            vec![0.; shift_len].as_slice(),
+            &samples[..samples.len() - shift_len],
+        ]
+        .concat()
+    } else {
+        [&samples[shift_len..], vec![0.; shift_len].as_slice()].concat()
+    }
+}
+fn to_wav_buffer(spec: &WavSpec, samples: &[f32]) -> Result<Vec<u8>, String> {
+    let mut buffer = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut buffer, *spec).map_err(|err| err.to_string())?;
+    for sample in samples {
+        writer
+            .write_sample(*sample)
+            .map_err(|err| err.to_string())?;
+    }
+    writer.finalize().map_err(|err| err.to_string())?;
+    Ok(buffer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shift_samples_delays_or_advances_the_audio() {
+        let samples = [1., 2., 3., 4.];
+        assert_eq!(shift_samples(&samples, 1, 0), vec![1., 2., 3., 4.]);
+        assert_eq!(shift_samples(&samples, 1, 1), vec![0., 1., 2., 3.]);
+        assert_eq!(shift_samples(&samples, 1, -2), vec![3., 4., 0., 0.]);
+    }
+
+    #[test]
+    fn shift_samples_moves_whole_frames() {
+        let samples = [1., -1., 2., -2., 3., -3.];
+        assert_eq!(
+            shift_samples(&samples, 2, 1),
+            vec![0., 0., 1., -1., 2., -2.]
+        );
+        assert_eq!(
+            shift_samples(&samples, 2, -1),
+            vec![2., -2., 3., -3., 0., 0.]
+        );
+    }
+
+    #[test]
+    fn shift_samples_beyond_the_length_is_silence() {
+        let samples = [1., 2., 3.];
+        assert_eq!(shift_samples(&samples, 1, 3), vec![0.; 3]);
+        assert_eq!(shift_samples(&samples, 1, -10), vec![0.; 3]);
+        assert!(shift_samples(&[], 1, 1).is_empty());
+    }
+
+    #[test]
+    fn change_speed_keeps_the_duration() {
+        let samples = [0., 1., 2., 3., 4., 5.];
+        assert_eq!(change_speed(&samples, 1, 1.), samples.to_vec());
+        assert_eq!(change_speed(&samples, 1, 2.), vec![0., 2., 4., 0., 0., 0.]);
+        assert_eq!(
+            change_speed(&samples, 1, 0.5),
+            vec![0., 0.5, 1., 1.5, 2., 2.5]
+        );
+    }
+
+    #[test]
+    fn change_speed_interpolates_each_channel() {
+        let samples = [0., 10., 2., 12., 4., 14.];
+        assert_eq!(
+            change_speed(&samples, 2, 0.5),
+            vec![0., 10., 1., 11., 2., 12.]
+        );
+        assert!(change_speed(&[], 2, 1.5).is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+
+use clap::Args;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rustpotter::{Rustpotter, RustpotterConfig};
+use serde::Serialize;
+
+use super::{audio::read_samples, spot::read_wakeword_info, test::DetectorSettings};
+
+#[derive(Args, Debug)]
+/// Measure the cpu time and memory used to process audio with each wakeword file.
+#[clap()]
+pub struct BenchCommand {
+    #[clap(num_args = 1.., required = true)]
+    /// Model path list.
+    model_path: Vec<String>,
+    #[clap(long)]
+    /// Wav record used as input, looped until the duration is reached. White noise is used if unset.
+    sample_path: Option<String>,
+    #[clap(long, conflicts_with = "sample_path")]
+    /// Seed used to generate the white noise, a random one is used and reported if unset.
+    seed: Option<u64>,
+    #[clap(long, default_value_t = 60)]
+    /// Seconds of audio processed with each wakeword file.
+    duration_secs: usize,
+    #[clap(flatten)]
+    detector: DetectorSettings,
+    #[clap(long)]
+    /// Print the results as json.
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct BenchReport {
+    arch: &'static str,
+    os: &'static str,
+    audio_secs: usize,
+    /// Seed of the white noise, unset when a wav record is used.
+    seed: Option<u64>,
+    results: Vec<BenchResult>,
+}
+#[derive(Serialize)]
+struct BenchResult {
+    path: String,
+    /// Wakeword type, "reference" or the model type.
+    kind: String,
+    /// Number of samples of wakeword references.
+    samples: Option<usize>,
+    file_size: u64,
+    frames: usize,
+    mean_frame_us: f64,
+    p99_frame_us: f64,
+    max_frame_us: f64,
+    /// Processing time divided by the audio duration.
+    real_time_factor: f64,
+    /// Peak resident memory of the whole process (VmHWM), which includes the loaded input audio,
+    /// only available on linux.
+    peak_memory_kb: Option<u64>,
+}
+
+pub fn bench(command: BenchCommand) -> Result<(), String> {
+    let seed = command
+        .sample_path
+        .is_none()
+        .then(|| command.seed.unwrap_or_else(|| rand::thread_rng().gen()));
+    let (config, samples) = match command.sample_path.as_ref() {
+        Some(sample_path) => {
+            let (wav_specs, samples) = read_samples(sample_path)?;
+            if samples.is_empty() {
+                return Err("Empty wav record ".to_owned() + sample_path);
+            }
+            let config = RustpotterConfig {
+                fmt: wav_specs.try_into()?,
+                ..Default::default()
+            };
+            (config, samples)
+        }
+        None => {
+            let config = RustpotterConfig::default();
+            // one second of white noise at the default format
+            let mut rng = StdRng::seed_from_u64(seed.unwrap_or_default());
+            let samples = (0..config.fmt.sample_rate)
+                .map(|_| rng.gen_range(-0.1..0.1))
+                .collect::<Vec<f32>>();
+            (config, samples)
+        }
+    };
+    let mut config = config;
+    command.detector.apply(&mut config);
+    let mut results = Vec::new();
+    for model_path in &command.model_path {
+        if !command.json {
+            println!("Benchmarking {}...", model_path);
+        }
+        results.push(run_bench(
+            model_path,
+            &config,
+            &samples,
+            command.duration_secs,
+        )?);
+    }
+    let report = BenchReport {
+        arch: std::env::consts::ARCH,
+        os: std::env::consts::OS,
+        audio_secs: command.duration_secs,
+        seed,
+        results,
+    };
+    if command.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(|err| err.to_string())?
+        );
+    } else {
+        print_report(&report);
+    }
+    Ok(())
+}
+
+fn run_bench(
+    model_path: &str,
+    config: &RustpotterConfig,
+    samples: &[f32],
+    duration_secs: usize,
+) -> Result<BenchResult, String> {
+    let wakeword_info = read_wakeword_info(model_path)?;
+    let file_size = std::fs::metadata(model_path)
+        .map_err(|err| err.to_string())?
+        .len();
+    reset_peak_memory();
+    let mut rustpotter = Rustpotter::new(config)?;
+    rustpotter.add_wakeword_from_file(model_path, model_path)?;
+    let samples_per_frame = rustpotter.get_samples_per_frame();
+    let frame_duration = Duration::from_secs_f64(
+        samples_per_frame as f64 / (config.fmt.sample_rate * config.fmt.channels as usize) as f64,
+    );
+    let frames = (Duration::from_secs(duration_secs as u64).as_secs_f64()
+        / frame_duration.as_secs_f64())
+    .ceil() as usize;
+    let mut frame_times = Vec::with_capacity(frames);
+    let mut offset = 0;
+    for _ in 0..frames {
+        // the input wraps around when the frame reaches its end
+        let mut frame = Vec::with_capacity(samples_per_frame);
+        while frame.len() < samples_per_frame {
+            let end = (offset + samples_per_frame - frame.len()).min(samples.len());
+            frame.extend_from_slice(&samples[offset..end]);
+            offset = end % samples.len();
+        }
+        let started = Instant::now();
+        rustpotter.process_samples(frame);
+        frame_times.push(started.elapsed());
+    }
+    let peak_memory_kb = get_peak_memory_kb();
+    let total_time = frame_times.iter().sum::<Duration>();
+    frame_times.sort();
+    let to_us = |duration: &Duration| duration.as_secs_f64() * 1_000_000.;
+    Ok(BenchResult {
+        path: model_path.to_string(),
+        kind: wakeword_info.kind,
+        samples: wakeword_info.samples,
+        file_size,
+        frames,
+        mean_frame_us: to_us(&total_time) / frames.max(1) as f64,
+        p99_frame_us: frame_times
+            .get((frames * 99 / 100).min(frames.saturating_sub(1)))
+            .map_or(0., to_us),
+        max_frame_us: frame_times.last().map_or(0., to_us),
+        real_time_factor: total_time.as_secs_f64()
+            / (frame_duration.as_secs_f64() * frames.max(1) as f64),
+        peak_memory_kb,
+    })
+}
+
+fn print_report(report: &BenchReport) {
+    println!(
+        "Processed {}s of audio per wakeword file ({}/{}):",
+        report.audio_secs, report.arch, report.os
+    );
+    if let Some(seed) = report.seed {
+        println!("White noise seed: {}", seed);
+    }
+    for result in &report.results {
+        let kind = match result.samples {
+            Some(samples) => format!("{} with {} samples", result.kind, samples),
+            None => format!("{} model", result.kind),
+        };
+        println!("{} ({}, {} bytes)", result.path, kind, result.file_size);
+        println!(
+            "  frame time: mean {:.1}us, p99 {:.1}us, max {:.1}us",
+            result.mean_frame_us, result.p99_frame_us, result.max_frame_us
+        );
+        println!("  real time factor: {:.4}", result.real_time_factor);
+        if let Some(peak_memory_kb) = result.peak_memory_kb {
+            println!("  peak process memory: {}KB", peak_memory_kb);
+        }
+    }
+}
+
+/// Resets the peak resident memory of the process, so each wakeword file is measured apart.
+fn reset_peak_memory() {
+    if cfg!(target_os = "linux") {
+        _ = std::fs::write("/proc/self/clear_refs", "5");
+    }
+}
+fn get_peak_memory_kb() -> Option<u64> {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
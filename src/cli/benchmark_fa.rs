@@ -0,0 +1,172 @@
+use std::{fs::File, io::BufReader, path::Path};
+
+use clap::Args;
+use hound::{SampleFormat, WavReader, WavWriter};
+
+use super::audio::get_wav_paths;
+use super::provenance::ProvenanceFilter;
+use super::test::{
+    check_wav_file, get_time_string, run_file_detection, DetectorSettings, FileDetection,
+    RunOptions,
+};
+
+#[derive(Args, Debug)]
+/// Measure the false alarms per hour of the models on audio without wakewords.
+#[clap()]
+pub struct BenchmarkFaCommand {
+    #[clap(long = "model", required = true)]
+    /// Model path, can be repeated.
+    model_path: Vec<String>,
+    #[clap()]
+    /// Directory containing the wav records, none of them should contain the wakewords.
+    noise_dir: String,
+    #[clap(flatten)]
+    detector: DetectorSettings,
+    #[clap(flatten)]
+    provenance_filter: ProvenanceFilter,
+    #[clap(long)]
+    /// Directory to export a clip for each false alarm, named to be used as "[none]" training data.
+    clips_dir: Option<String>,
+    #[clap(long, default_value_t = 250)]
+    /// Milliseconds of audio added before and after the detection on the exported clips.
+    clip_padding_ms: usize,
+}
+
+pub fn benchmark_fa(command: BenchmarkFaCommand) -> Result<(), String> {
+    let sample_paths = command
+        .provenance_filter
+        .filter_paths(get_wav_paths(&command.noise_dir)?)?;
+    if sample_paths.is_empty() {
+        return Err("No wav records found in ".to_owned() + &command.noise_dir);
+    }
+    if let Some(clips_dir) = command.clips_dir.as_ref() {
+        std::fs::create_dir_all(clips_dir).map_err(|err| err.to_string())?;
+    }
+    println!(
+        "Benchmarking models {:?} on {} records from {}!",
+        command.model_path,
+        sample_paths.len(),
+        command.noise_dir
+    );
+    let options = RunOptions {
+        tail_ms: 3000,
+        quiet: true,
+        ..Default::default()
+    };
+    let mut total_duration_ms = 0;
+    let mut total_false_alarms = 0;
+    let mut skipped_records = 0;
+    for sample_path in &sample_paths {
+        // an unreadable record is reported and excluded from the totals, the model errors abort the benchmark
+        if let Err(err) = check_wav_file(sample_path) {
+            println!("{}: skipped, {}", sample_path, err);
+            skipped_records += 1;
+            continue;
+        }
+        let detections = run_file_detection(
+            sample_path,
+            &command.model_path,
+            &command.detector,
+            &options,
+        )?;
+        let duration_ms = get_duration_ms(sample_path)?;
+        println!(
+            "{}: {} false alarms in {} ({:.2}/h)",
+            sample_path,
+            detections.len(),
+            get_time_string(duration_ms),
+            get_rate_per_hour(detections.len(), duration_ms)
+        );
+        for detection in &detections {
+            print!(
+                "  {}: {} - {} score {:.3} ({})",
+                detection.name,
+                get_time_string(detection.start_ms),
+                get_time_string(detection.end_ms),
+                detection.score,
+                detection.model_path
+            );
+            if let Some(clips_dir) = command.clips_dir.as_ref() {
+                let clip_path =
+                    export_clip(sample_path, clips_dir, detection, command.clip_padding_ms)?;
+                print!(" -> {}", clip_path);
+            }
+            println!();
+        }
+        total_duration_ms += duration_ms;
+        total_false_alarms += detections.len();
+    }
+    if skipped_records == sample_paths.len() {
+        return Err("None of the wav records could be read".to_string());
+    }
+    println!(
+        "Total: {} false alarms in {} ({:.2}/h)",
+        total_false_alarms,
+        get_time_string(total_duration_ms),
+        get_rate_per_hour(total_false_alarms, total_duration_ms)
+    );
+    Ok(())
+}
+
+fn get_duration_ms(path: &str) -> Result<usize, String> {
+    let wav_reader = WavReader::open(path).map_err(|err| err.to_string())?;
+    Ok(wav_reader.duration() as usize * 1000 / wav_reader.spec().sample_rate as usize)
+}
+
+fn get_rate_per_hour(count: usize, duration_ms: usize) -> f64 {
+    count as f64 * 3_600_000. / duration_ms.max(1) as f64
+}
+
+/// Copies the detection audio into a new wav file, returns its path.
+fn export_clip(
+    sample_path: &str,
+    clips_dir: &str,
+    detection: &FileDetection,
+    padding_ms: usize,
+) -> Result<String, String> {
+    let mut wav_reader = WavReader::open(sample_path).map_err(|err| err.to_string())?;
+    let spec = wav_reader.spec();
+    let sample_rate = spec.sample_rate as usize;
+    let duration = wav_reader.duration() as usize;
+    let start = (detection.start_ms.saturating_sub(padding_ms) * sample_rate / 1000).min(duration);
+    let end = ((detection.end_ms + padding_ms) * sample_rate / 1000).min(duration);
+    wav_reader
+        .seek(start as u32)
+        .map_err(|err| err.to_string())?;
+    let file_stem = Path::new(sample_path)
+        .file_stem()
+        .map_or("clip".into(), |stem| stem.to_string_lossy());
+    let clip_path = Path::new(clips_dir)
+        .join(format!(
+            "[none]{}-{}ms-{}.wav",
+            file_stem,
+            detection.start_ms,
+            format!("{:.3}", detection.score).replace('.', "_")
+        ))
+        .to_string_lossy()
+        .to_string();
+    let mut writer = WavWriter::create(&clip_path, spec).map_err(|err| err.to_string())?;
+    let samples_len = (end - start) * spec.channels as usize;
+    match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Int, 8) => copy_samples::<i8>(&mut wav_reader, &mut writer, samples_len),
+        (SampleFormat::Int, 16) => copy_samples::<i16>(&mut wav_reader, &mut writer, samples_len),
+        (SampleFormat::Int, 32) => copy_samples::<i32>(&mut wav_reader, &mut writer, samples_len),
+        (SampleFormat::Float, 32) => copy_samples::<f32>(&mut wav_reader, &mut writer, samples_len),
+        _ => Err("Unsupported wav format".to_string()),
+    }?;
+    writer.finalize().map_err(|err| err.to_string())?;
+    Ok(clip_path)
+}
+
+fn copy_samples<T: hound::Sample>(
+    wav_reader: &mut WavReader<BufReader<File>>,
+    writer: &mut WavWriter<std::io::BufWriter<File>>,
+    samples_len: usize,
+) -> Result<(), String> {
+    for sample in wav_reader.samples::<T>().take(samples_len) {
+        writer
+            .write_sample(sample.map_err(|err| err.to_string())?)
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
@@ -1,8 +1,20 @@
-use std::{fs::File, io::BufReader};
+use std::{collections::HashMap, fs::File, io::BufReader, path::Path};
 
 use clap::Args;
 use hound::WavReader;
-use rustpotter::{WakewordRef, WakewordRefBuildFromFiles, WakewordSave};
+use rustpotter::{
+    WakewordRef, WakewordRefBuildFromBuffers, WakewordRefBuildFromFiles, WakewordSave,
+};
+
+use super::{
+    analyze::{analyze, AnalyzeSettings},
+    audio::read_samples,
+    calibrate::{calibrate, CalibrateSettings},
+    filter::{filter_sample, get_median_rms_level, get_sample_rms_level, SampleFilterSettings},
+    metadata::{get_samples_dir, write_reference_samples, WakewordMetadata},
+    provenance::ProvenanceFilter,
+    quality::{check_formats, check_sample, trim_sample, QualitySettings},
+};
 
 #[derive(Args, Debug)]
 /// Creates a wakeword reference using wav audio files.
@@ -26,25 +38,153 @@ pub struct BuildCommand {
     #[clap(short = 'c', long, default_value_t = 16)]
     /// Number of extracted mel-frequency cepstral coefficients
     mfcc_size: u16,
+    #[clap(flatten)]
+    provenance_filter: ProvenanceFilter,
+    #[clap(flatten)]
+    quality: QualitySettings,
+    #[clap(flatten)]
+    calibrate: CalibrateSettings,
+    #[clap(flatten)]
+    analyze: AnalyzeSettings,
+    #[clap(flatten)]
+    filters: SampleFilterSettings,
 }
 pub fn build_ref(command: BuildCommand) -> Result<(), String> {
     println!("Start building {}!", command.path);
-    println!("From samples:");
-    for path in &command.sample_path {
+    let mut sample_paths = command
+        .provenance_filter
+        .filter_paths(command.sample_path)?;
+    if sample_paths.is_empty() {
+        return Err("No samples match the filters".to_string());
+    }
+    println!("Checking samples:");
+    let mut reports = Vec::with_capacity(sample_paths.len());
+    let mut samples_list = Vec::with_capacity(sample_paths.len());
+    for path in &sample_paths {
         let reader = BufReader::new(File::open(path).map_err(|err| err.to_string())?);
         let wav_spec = WavReader::new(reader)
             .map_err(|err| err.to_string())?
             .spec();
-        println!("{}: {:?}", path, wav_spec);
-    }
-    let wakeword = WakewordRef::new_from_sample_files(
-        command.name.clone(),
-        command.threshold,
-        command.averaged_threshold,
-        command.sample_path,
-        command.mfcc_size,
-    )?;
+        let (_, samples) = read_samples(path)?;
+        reports.push(check_sample(path, &wav_spec, &samples, &command.quality));
+        samples_list.push(samples);
+    }
+    check_formats(&mut reports);
+    for report in &reports {
+        println!("{}", report);
+    }
+    let errors = reports
+        .iter()
+        .map(|report| report.errors.len())
+        .sum::<usize>();
+    let warnings = reports
+        .iter()
+        .map(|report| report.warnings.len())
+        .sum::<usize>();
+    println!(
+        "{} samples checked, {} errors, {} warnings.",
+        reports.len(),
+        errors,
+        warnings
+    );
+    if errors != 0 && !command.quality.force {
+        return Err(
+            "Some samples have errors, fix or remove them, or use --force to build anyway"
+                .to_string(),
+        );
+    }
+    let filters_enabled = command.filters.is_enabled();
+    let from_buffers = command.quality.trim || filters_enabled;
+    let mut samples_buffers = Vec::new();
+    for (report, samples) in reports.iter().zip(&samples_list) {
+        let file_name = Path::new(&report.path)
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let buffer = if command.quality.trim {
+            trim_sample(report, samples)?
+        } else {
+            std::fs::read(&report.path).map_err(|err| err.to_string())?
+        };
+        samples_buffers.push((file_name, buffer));
+    }
+    // the detector measures the rms level before filtering, so the reference keeps the unfiltered one
+    let sample_rms_levels = samples_buffers
+        .iter()
+        .map(|(name, buffer)| Ok((name.clone(), get_sample_rms_level(buffer)?)))
+        .collect::<Result<HashMap<_, _>, String>>()?;
+    if filters_enabled {
+        let rms_level = get_median_rms_level(sample_rms_levels.values().copied());
+        for (_, buffer) in samples_buffers.iter_mut() {
+            *buffer = filter_sample(buffer, &command.filters, rms_level)?;
+        }
+    }
+    if command.analyze.analyze {
+        let outliers = analyze(&samples_buffers, command.mfcc_size)?;
+        if command.analyze.drop_outliers && !outliers.is_empty() {
+            println!("Building without the outliers.");
+            samples_buffers.retain(|(name, _)| !outliers.contains(name));
+            sample_paths.retain(|path| {
+                Path::new(path).file_name().is_none_or(|file_name| {
+                    !outliers.contains(&file_name.to_string_lossy().to_string())
+                })
+            });
+        }
+    }
+    let mut wakeword = if from_buffers {
+        WakewordRef::new_from_sample_buffers(
+            command.name.clone(),
+            command.threshold,
+            command.averaged_threshold,
+            samples_buffers.iter().cloned().collect::<HashMap<_, _>>(),
+            command.mfcc_size,
+        )?
+    } else {
+        WakewordRef::new_from_sample_files(
+            command.name.clone(),
+            command.threshold,
+            command.averaged_threshold,
+            sample_paths,
+            command.mfcc_size,
+        )?
+    };
+    // the median of the kept samples, as the library does when building from files
+    wakeword.rms_level = get_median_rms_level(
+        samples_buffers
+            .iter()
+            .map(|(name, _)| sample_rms_levels[name]),
+    );
+    if command.calibrate.calibrate {
+        let calibration = calibrate(
+            &wakeword,
+            &samples_buffers,
+            &command.calibrate,
+            &command.filters,
+        )?;
+        wakeword.threshold = command.threshold.or(Some(calibration.threshold));
+        wakeword.avg_threshold = command
+            .averaged_threshold
+            .or(Some(calibration.avg_threshold));
+    }
     wakeword.save_to_file(&command.path)?;
+    let mut metadata = WakewordMetadata::new();
+    if filters_enabled {
+        metadata.filters = Some(command.filters);
+    }
+    metadata.sample_rms_levels = samples_buffers
+        .iter()
+        .map(|(name, _)| (name.clone(), sample_rms_levels[name]))
+        .collect();
+    metadata.write(&command.path)?;
+    // the ref command rebuilds the averaged features from them
+    let samples_dir = get_samples_dir(&command.path);
+    if samples_dir.exists() {
+        std::fs::remove_dir_all(&samples_dir).map_err(|err| err.to_string())?;
+    }
+    write_reference_samples(
+        &command.path,
+        samples_buffers.iter().map(|(name, buffer)| (name, buffer)),
+    )?;
     println!("{} created!", command.name);
     Ok(())
 }
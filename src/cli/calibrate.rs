@@ -0,0 +1,213 @@
+use std::{collections::HashMap, io::Cursor};
+
+use clap::Args;
+use hound::{WavReader, WavSpec};
+use rustpotter::{
+    Rustpotter, RustpotterConfig, WakewordLoad, WakewordRef, WakewordRefBuildFromBuffers,
+    WakewordSave,
+};
+
+use super::{
+    audio::{get_wav_paths, read_samples, read_wav_samples},
+    filter::{filter_sample, SampleFilterSettings},
+    test::DetectorSettings,
+};
+
+/// Milliseconds of silence added around the scored records, so the detector window can cover them.
+const CALIBRATION_PADDING_MS: usize = 1500;
+/// Fraction of the lowest positive score recommended when there are no negative records.
+const POSITIVE_MARGIN: f32 = 0.9;
+/// Score added to the highest negative one when it is over the lowest positive one.
+const NEGATIVE_MARGIN: f32 = 0.02;
+
+#[derive(Args, Debug)]
+/// Options to recommend the wakeword thresholds from its samples.
+pub(crate) struct CalibrateSettings {
+    #[clap(long)]
+    /// Score each sample against a reference built from the others, and write the recommended thresholds
+    /// unless they are provided.
+    pub calibrate: bool,
+    #[clap(long, requires = "calibrate")]
+    /// Directory containing wav records without the wakeword, scored against the reference to recommend the thresholds.
+    negatives: Option<String>,
+}
+
+/// Thresholds recommended by the calibration.
+pub(crate) struct Calibration {
+    pub threshold: f32,
+    pub avg_threshold: f32,
+}
+
+/// Max scores of a record against a wakeword reference.
+pub(crate) struct Scores {
+    pub name: String,
+    pub score: f32,
+    pub avg_score: f32,
+}
+
+/// Scores the samples with leave-one-out references and the negative records with the full one, returning the recommended thresholds.
+/// The samples are expected to be filtered already.
+pub(crate) fn calibrate(
+    wakeword: &WakewordRef,
+    samples_buffers: &[(String, Vec<u8>)],
+    settings: &CalibrateSettings,
+    filters: &SampleFilterSettings,
+) -> Result<Calibration, String> {
+    if samples_buffers.len() < 2 {
+        return Err("At least two samples are required to calibrate".to_string());
+    }
+    println!("Calibrating with leave-one-out scoring:");
+    let mut positive_scores = Vec::with_capacity(samples_buffers.len());
+    for (name, buffer) in samples_buffers {
+        let other_samples = samples_buffers
+            .iter()
+            .filter(|(other_name, _)| other_name != name)
+            .cloned()
+            .collect::<HashMap<_, _>>();
+        let sample_wakeword = WakewordRef::new_from_sample_buffers(
+            wakeword.name.clone(),
+            None,
+            None,
+            other_samples,
+            wakeword.mfcc_size,
+        )?;
+        let mut wav_reader = WavReader::new(Cursor::new(buffer)).map_err(|err| err.to_string())?;
+        let (spec, samples) = read_wav_samples(&mut wav_reader)?;
+        positive_scores.push(get_scores(name, sample_wakeword, &spec, &samples)?);
+    }
+    let mut negative_scores = Vec::new();
+    if let Some(negatives_dir) = settings.negatives.as_ref() {
+        let wakeword_buffer = wakeword.save_to_buffer()?;
+        for path in get_wav_paths(negatives_dir)? {
+            // the samples are already filtered, so the negative records get the same filters
+            let (spec, samples) = if filters.is_enabled() {
+                let buffer = std::fs::read(&path).map_err(|err| err.to_string())?;
+                let filtered_buffer = filter_sample(&buffer, filters, wakeword.rms_level)?;
+                read_wav_samples(
+                    &mut WavReader::new(Cursor::new(filtered_buffer))
+                        .map_err(|err| err.to_string())?,
+                )?
+            } else {
+                read_samples(&path)?
+            };
+            let mut negative_wakeword = WakewordRef::load_from_buffer(&wakeword_buffer)?;
+            negative_wakeword.threshold = None;
+            negative_wakeword.avg_threshold = None;
+            negative_scores.push(get_scores(&path, negative_wakeword, &spec, &samples)?);
+        }
+        if negative_scores.is_empty() {
+            return Err("No wav records found in ".to_owned() + negatives_dir);
+        }
+    }
+    let highest_negative = negative_scores
+        .iter()
+        .max_by(|a, b| a.score.total_cmp(&b.score));
+    let max_negative_avg_score = negative_scores
+        .iter()
+        .map(|scores| scores.avg_score)
+        .fold(0., f32::max);
+    for scores in &positive_scores {
+        print!(
+            "{}: score {:.3}, averaged score {:.3}",
+            scores.name, scores.score, scores.avg_score
+        );
+        match highest_negative {
+            Some(highest_negative) => {
+                println!(", margin {:.3}", scores.score - highest_negative.score)
+            }
+            None => println!(),
+        }
+    }
+    let lowest_positive = positive_scores
+        .iter()
+        .min_by(|a, b| a.score.total_cmp(&b.score))
+        .expect("Missing positive scores");
+    let min_positive_avg_score = positive_scores
+        .iter()
+        .map(|scores| scores.avg_score)
+        .fold(f32::MAX, f32::min);
+    println!(
+        "Lowest positive score {:.3} ({}), lowest averaged score {:.3}.",
+        lowest_positive.score, lowest_positive.name, min_positive_avg_score
+    );
+    if let Some(highest_negative) = highest_negative {
+        println!(
+            "Highest negative score {:.3} ({}), highest averaged score {:.3}, on {} records.",
+            highest_negative.score,
+            highest_negative.name,
+            max_negative_avg_score,
+            negative_scores.len()
+        );
+    }
+    let calibration = Calibration {
+        threshold: recommend_threshold(
+            lowest_positive.score,
+            highest_negative.map(|scores| scores.score),
+        ),
+        avg_threshold: recommend_threshold(
+            min_positive_avg_score,
+            highest_negative.map(|_| max_negative_avg_score),
+        ),
+    };
+    let missed_samples = positive_scores
+        .iter()
+        .filter(|scores| scores.score < calibration.threshold)
+        .count();
+    if missed_samples != 0 {
+        println!(
+            "Warning: positive and negative scores overlap, {} samples score under the recommended threshold.",
+            missed_samples
+        );
+    }
+    println!(
+        "Recommended threshold {:.3}, averaged threshold {:.3}.",
+        calibration.threshold, calibration.avg_threshold
+    );
+    Ok(calibration)
+}
+/// Returns a threshold between the positive and negative scores, or over the negative one if they overlap.
+fn recommend_threshold(min_positive: f32, max_negative: Option<f32>) -> f32 {
+    match max_negative {
+        Some(max_negative) if max_negative < min_positive => (min_positive + max_negative) / 2.,
+        Some(max_negative) => (max_negative + NEGATIVE_MARGIN).min(1.),
+        None => min_positive * POSITIVE_MARGIN,
+    }
+}
+/// Returns the max scores of the record against the wakeword, using the default detector settings without thresholds.
+pub(crate) fn get_scores(
+    name: &str,
+    wakeword: WakewordRef,
+    spec: &WavSpec,
+    samples: &[f32],
+) -> Result<Scores, String> {
+    let mut config = RustpotterConfig {
+        fmt: (*spec).try_into()?,
+        ..Default::default()
+    };
+    DetectorSettings::default().apply(&mut config);
+    config.detector.threshold = 0.;
+    // a zero averaged threshold disables the averaged score
+    config.detector.avg_threshold = f32::MIN_POSITIVE;
+    let mut rustpotter = Rustpotter::new(&config)?;
+    rustpotter.add_wakeword_ref(name, wakeword)?;
+    let padding = vec![
+        0.;
+        spec.sample_rate as usize * spec.channels as usize * CALIBRATION_PADDING_MS
+            / 1000
+    ];
+    let padded_samples = [padding.as_slice(), samples, padding.as_slice()].concat();
+    let mut scores = Scores {
+        name: name.to_string(),
+        score: 0.,
+        avg_score: 0.,
+    };
+    for frame in padded_samples.chunks_exact(rustpotter.get_samples_per_frame()) {
+        rustpotter.process_samples(frame.to_vec());
+        // without threshold every frame scores, so the partial detection is never completed
+        if let Some(detection) = rustpotter.get_partial_detection() {
+            scores.score = scores.score.max(detection.score);
+            scores.avg_score = scores.avg_score.max(detection.avg_score);
+        }
+    }
+    Ok(scores)
+}
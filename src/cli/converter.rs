@@ -0,0 +1,200 @@
+use clap::Args;
+use hound::WavSpec;
+use rubato::{FftFixedIn, Resampler};
+use rustpotter::{SampleFormat, DETECTOR_INTERNAL_SAMPLE_RATE};
+
+/// Input frames processed on each resampler call.
+const RESAMPLER_CHUNK_SIZE: usize = 1024;
+
+#[derive(Args, Debug)]
+/// Format of the written audio, converted from the device one.
+pub(crate) struct OutputFormat {
+    #[clap(long)]
+    /// Sample rate of the written audio, resampled from the device one.
+    out_rate: Option<u32>,
+    #[clap(long)]
+    /// Number of channels of the written audio, a single channel is the average of the device ones.
+    out_channels: Option<u16>,
+    #[clap(long, value_parser = parse_sample_format)]
+    /// Sample format of the written audio (i8, i16, i32 or f32).
+    out_format: Option<SampleFormat>,
+    #[clap(long, conflicts_with_all = ["out_rate", "out_channels", "out_format"])]
+    /// Write audio in the detector internal format, 16000Hz mono i16.
+    detector_format: bool,
+}
+impl OutputFormat {
+    /// Returns the spec of the written audio for the device one.
+    pub(crate) fn get_spec(&self, device_spec: &WavSpec) -> WavSpec {
+        let (sample_rate, channels, sample_format) = if self.detector_format {
+            (
+                Some(DETECTOR_INTERNAL_SAMPLE_RATE as u32),
+                Some(1),
+                Some(SampleFormat::I16),
+            )
+        } else {
+            (self.out_rate, self.out_channels, self.out_format.clone())
+        };
+        WavSpec {
+            sample_rate: sample_rate.unwrap_or(device_spec.sample_rate),
+            channels: channels.unwrap_or(device_spec.channels),
+            bits_per_sample: sample_format.as_ref().map_or(
+                device_spec.bits_per_sample,
+                SampleFormat::get_bits_per_sample,
+            ),
+            sample_format: match sample_format {
+                Some(SampleFormat::F32) => hound::SampleFormat::Float,
+                Some(_) => hound::SampleFormat::Int,
+                None => device_spec.sample_format,
+            },
+        }
+    }
+}
+fn parse_sample_format(value: &str) -> Result<SampleFormat, String> {
+    match value {
+        "i8" => Ok(SampleFormat::I8),
+        "i16" => Ok(SampleFormat::I16),
+        "i32" => Ok(SampleFormat::I32),
+        "f32" => Ok(SampleFormat::F32),
+        _ => Err(format!("Unsupported sample format '{}'", value)),
+    }
+}
+
+/// Converts interleaved float samples between channel numbers and sample rates.
+pub(crate) struct AudioConverter {
+    output_spec: WavSpec,
+    input_channels: usize,
+    output_channels: usize,
+    ratio: f64,
+    resampler: Option<FftFixedIn<f32>>,
+    /// Input frames, per output channel, waiting to complete a resampler chunk.
+    pending: Vec<Vec<f32>>,
+    /// Resampled frames to discard, the resampler introduces this delay.
+    delay_frames: usize,
+    input_frames: usize,
+    output_frames: usize,
+}
+impl AudioConverter {
+    pub(crate) fn new(input_spec: &WavSpec, output_spec: &WavSpec) -> Result<Self, String> {
+        let output_channels = output_spec.channels as usize;
+        let resampler = if input_spec.sample_rate != output_spec.sample_rate {
+            Some(
+                FftFixedIn::new(
+                    input_spec.sample_rate as usize,
+                    output_spec.sample_rate as usize,
+                    RESAMPLER_CHUNK_SIZE,
+                    2,
+                    output_channels,
+                )
+                .map_err(|err| err.to_string())?,
+            )
+        } else {
+            None
+        };
+        Ok(AudioConverter {
+            output_spec: *output_spec,
+            input_channels: input_spec.channels as usize,
+            output_channels,
+            ratio: output_spec.sample_rate as f64 / input_spec.sample_rate as f64,
+            delay_frames: resampler
+                .as_ref()
+                .map_or(0, |resampler| resampler.output_delay()),
+            resampler,
+            pending: vec![Vec::new(); output_channels],
+            input_frames: 0,
+            output_frames: 0,
+        })
+    }
+    /// Returns the spec of the converted samples.
+    pub(crate) fn get_output_spec(&self) -> &WavSpec {
+        &self.output_spec
+    }
+    /// Returns the converted samples available after adding the provided ones.
+    pub(crate) fn process(&mut self, samples: &[f32]) -> Result<Vec<f32>, String> {
+        for frame in samples.chunks_exact(self.input_channels) {
+            self.input_frames += 1;
+            if self.output_channels == 1 {
+                self.pending[0].push(frame.iter().sum::<f32>() / self.input_channels as f32);
+            } else {
+                for (channel, pending) in self.pending.iter_mut().enumerate() {
+                    pending.push(frame[channel % self.input_channels]);
+                }
+            }
+        }
+        let Some(resampler) = self.resampler.as_mut() else {
+            return Ok(self.take_pending());
+        };
+        let mut resampled_chunks = Vec::new();
+        while self.pending[0].len() >= resampler.input_frames_next() {
+            let chunk_size = resampler.input_frames_next();
+            let chunk = self
+                .pending
+                .iter_mut()
+                .map(|pending| pending.drain(..chunk_size).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            resampled_chunks.push(
+                resampler
+                    .process(&chunk, None)
+                    .map_err(|err| err.to_string())?,
+            );
+        }
+        let mut output = Vec::new();
+        for resampled in resampled_chunks {
+            self.append_resampled(resampled, &mut output);
+        }
+        Ok(output)
+    }
+    /// Returns the converted samples still buffered, no more samples should be added.
+    pub(crate) fn finish(&mut self) -> Result<Vec<f32>, String> {
+        let Some(resampler) = self.resampler.as_mut() else {
+            return Ok(self.take_pending());
+        };
+        let expected_frames = (self.input_frames as f64 * self.ratio).round() as usize;
+        let mut output = Vec::new();
+        let pending = std::mem::replace(&mut self.pending, vec![Vec::new(); self.output_channels]);
+        let mut resampled_chunks = vec![resampler
+            .process_partial(Some(&pending), None)
+            .map_err(|err| err.to_string())?];
+        // flush the frames retained by the resampler delay
+        while self.output_frames
+            + resampled_chunks
+                .iter()
+                .map(|chunk| chunk[0].len())
+                .sum::<usize>()
+            < expected_frames + self.delay_frames
+        {
+            let resampled = resampler
+                .process_partial::<Vec<f32>>(None, None)
+                .map_err(|err| err.to_string())?;
+            if resampled[0].is_empty() {
+                break;
+            }
+            resampled_chunks.push(resampled);
+        }
+        for resampled in resampled_chunks {
+            self.append_resampled(resampled, &mut output);
+        }
+        let excess_frames = self.output_frames.saturating_sub(expected_frames);
+        output.truncate(output.len() - (excess_frames * self.output_channels).min(output.len()));
+        self.output_frames -= excess_frames;
+        Ok(output)
+    }
+    fn take_pending(&mut self) -> Vec<f32> {
+        let frames = self.pending[0].len();
+        let mut output = Vec::with_capacity(frames * self.output_channels);
+        for index in 0..frames {
+            output.extend(self.pending.iter().map(|pending| pending[index]));
+        }
+        self.pending.iter_mut().for_each(Vec::clear);
+        self.output_frames += frames;
+        output
+    }
+    fn append_resampled(&mut self, resampled: Vec<Vec<f32>>, output: &mut Vec<f32>) {
+        let frames = resampled[0].len();
+        let skipped_frames = self.delay_frames.min(frames);
+        self.delay_frames -= skipped_frames;
+        for index in skipped_frames..frames {
+            output.extend(resampled.iter().map(|channel| channel[index]));
+        }
+        self.output_frames += frames - skipped_frames;
+    }
+}
@@ -0,0 +1,132 @@
+use std::convert::Infallible;
+
+use clap::Args;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::{
+    audio::{get_noise_segment, get_wav_paths, mix_noise, read_noises, read_samples},
+    provenance::ProvenanceFilter,
+    test::{run_samples_detection, DetectorSettings, RunOptions},
+};
+
+#[derive(Args, Debug)]
+/// Measure the detection rate of the models on wav records containing the wakewords.
+#[clap()]
+pub struct EvaluateCommand {
+    #[clap(long = "model", required = true)]
+    /// Model path, can be repeated.
+    model_path: Vec<String>,
+    #[clap()]
+    /// Directory containing the wav records, each of them should contain a wakeword.
+    samples_dir: String,
+    #[clap(flatten)]
+    detector: DetectorSettings,
+    #[clap(flatten)]
+    provenance_filter: ProvenanceFilter,
+    #[clap(long)]
+    /// Directory containing wav records of noise to mix into the samples.
+    noise_dir: Option<String>,
+    #[clap(
+        long,
+        value_delimiter = ',',
+        num_args = 1..,
+        default_value = "10",
+        requires = "noise_dir"
+    )]
+    /// Signal to noise ratios, in decibels, to evaluate when a noise directory is provided ("20,10,5,0").
+    snr_db: Vec<f32>,
+    #[clap(long)]
+    /// Seed used to choose the noise segments, a random one is used and printed if unset.
+    seed: Option<u64>,
+    #[clap(long, default_value_t = 3000)]
+    /// Milliseconds of silence appended after each record, so a detection at the end of the file can be emitted.
+    tail_ms: usize,
+    #[clap(long)]
+    /// Log the records without detections.
+    verbose: bool,
+}
+
+pub fn evaluate(command: EvaluateCommand) -> Result<(), String> {
+    let sample_paths = command
+        .provenance_filter
+        .filter_paths(get_wav_paths(&command.samples_dir)?)?;
+    if sample_paths.is_empty() {
+        return Err("No wav records found in ".to_owned() + &command.samples_dir);
+    }
+    println!(
+        "Evaluating models {:?} on {} records from {}!",
+        command.model_path,
+        sample_paths.len(),
+        command.samples_dir
+    );
+    let noises = match command.noise_dir.as_ref() {
+        Some(noise_dir) => read_noises(noise_dir)?,
+        None => Vec::new(),
+    };
+    let seed = command.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    if !noises.is_empty() {
+        println!("Mixing noise from {} records, seed {}", noises.len(), seed);
+    }
+    let mut rng = StdRng::seed_from_u64(seed);
+    let options = RunOptions {
+        tail_ms: command.tail_ms,
+        quiet: true,
+        ..Default::default()
+    };
+    let mut levels = vec![None];
+    if !noises.is_empty() {
+        levels.extend(command.snr_db.iter().copied().map(Some));
+    }
+    let mut detected_counts = vec![0; levels.len()];
+    let mut missed_records = vec![Vec::new(); levels.len()];
+    for sample_path in &sample_paths {
+        let (wav_specs, samples) = read_samples(sample_path)?;
+        // the same noise segment is used for every level, so only the ratio changes between them
+        let noise_segment = if noises.is_empty() {
+            Vec::new()
+        } else {
+            let noise = &noises[rng.gen_range(0..noises.len())];
+            get_noise_segment(
+                noise,
+                wav_specs.sample_rate as usize,
+                samples.len() / wav_specs.channels as usize,
+                &mut rng,
+            )
+        };
+        for (index, snr_db) in levels.iter().enumerate() {
+            let samples = match snr_db {
+                Some(snr_db) => mix_noise(&samples, wav_specs.channels, &noise_segment, *snr_db),
+                None => samples.clone(),
+            };
+            let time_range = 0..samples.len() / wav_specs.channels as usize;
+            let detections = run_samples_detection(
+                samples.into_iter().map(Ok::<f32, Infallible>),
+                &wav_specs,
+                time_range,
+                &command.model_path,
+                &command.detector,
+                &options,
+            )?;
+            if detections.is_empty() {
+                missed_records[index].push(sample_path);
+            } else {
+                detected_counts[index] += 1;
+            }
+        }
+    }
+    for (index, snr_db) in levels.iter().enumerate() {
+        println!(
+            "{}: {} of {} records detected ({:.2}%)",
+            snr_db.map_or("clean".to_string(), |snr_db| format!("snr {}dB", snr_db)),
+            detected_counts[index],
+            sample_paths.len(),
+            detected_counts[index] as f32 * 100. / sample_paths.len() as f32
+        );
+        if command.verbose {
+            missed_records[index]
+                .iter()
+                .for_each(|path| println!("  - missed {}", path));
+        }
+    }
+    Ok(())
+}
@@ -1,10 +1,23 @@
 use clap::Args;
-use hound::WavReader;
+use hound::{WavReader, WavSpec, WavWriter};
 use rustpotter::{
-    BandPassFilter, GainNormalizerFilter, Sample, SampleFormat, AudioEncoder, AudioFmt,
+    AudioEncoder, AudioFmt, BandPassFilter, GainNormalizerFilter, Sample, SampleFormat,
     DETECTOR_INTERNAL_SAMPLE_RATE, MFCCS_EXTRACTOR_FRAME_LENGTH_MS,
 };
-use std::{fs::File, io::BufReader, path::Path};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, Cursor, Read},
+    path::Path,
+};
+
+/// Spec of the audio once encoded for the detector.
+const INTERNAL_SPEC: WavSpec = WavSpec {
+    sample_rate: DETECTOR_INTERNAL_SAMPLE_RATE as u32,
+    bits_per_sample: 32,
+    sample_format: hound::SampleFormat::Float,
+    channels: 1,
+};
 
 #[derive(Args, Debug)]
 /// Apply the audio filters to a file
@@ -68,13 +81,7 @@ pub fn filter(command: FilterCommand) -> Result<(), String> {
         DETECTOR_INTERNAL_SAMPLE_RATE,
     )
     .unwrap();
-    let internal_spec = hound::WavSpec {
-        sample_rate: DETECTOR_INTERNAL_SAMPLE_RATE as u32,
-        bits_per_sample: 32,
-        sample_format: hound::SampleFormat::Float,
-        channels: 1,
-    };
-    let mut writer = hound::WavWriter::create(filtered_filename, internal_spec).unwrap();
+    let mut writer = hound::WavWriter::create(filtered_filename, INTERNAL_SPEC).unwrap();
     let mut gain_filter = GainNormalizerFilter::new(0.1, 1., Some(command.gain_ref));
     let mut bandpass_filter = BandPassFilter::new(
         DETECTOR_INTERNAL_SAMPLE_RATE as f32,
@@ -82,10 +89,10 @@ pub fn filter(command: FilterCommand) -> Result<(), String> {
         command.high_cutoff,
     );
     match wav_spec.sample_format {
-        SampleFormat::I8 => get_encoded_chucks::<i8>(&mut wav_reader, &mut encoder),
-        SampleFormat::I16 => get_encoded_chucks::<i16>(&mut wav_reader, &mut encoder),
-        SampleFormat::I32 => get_encoded_chucks::<i32>(&mut wav_reader, &mut encoder),
-        SampleFormat::F32 => get_encoded_chucks::<f32>(&mut wav_reader, &mut encoder),
+        SampleFormat::I8 => get_encoded_chucks::<i8, _>(&mut wav_reader, &mut encoder),
+        SampleFormat::I16 => get_encoded_chucks::<i16, _>(&mut wav_reader, &mut encoder),
+        SampleFormat::I32 => get_encoded_chucks::<i32, _>(&mut wav_reader, &mut encoder),
+        SampleFormat::F32 => get_encoded_chucks::<f32, _>(&mut wav_reader, &mut encoder),
     }
     .into_iter()
     .map(|mut chunk| {
@@ -107,8 +114,8 @@ pub fn filter(command: FilterCommand) -> Result<(), String> {
     Ok(())
 }
 
-fn get_encoded_chucks<T: Sample + hound::Sample>(
-    wav_reader: &mut WavReader<BufReader<File>>,
+fn get_encoded_chucks<T: Sample + hound::Sample, R: Read>(
+    wav_reader: &mut WavReader<R>,
     encoder: &mut AudioEncoder,
 ) -> Vec<Vec<f32>> {
     wav_reader
@@ -119,3 +126,107 @@ fn get_encoded_chucks<T: Sample + hound::Sample>(
         .map(|chuck| encoder.rencode_and_resample(chuck.to_vec()))
         .collect::<Vec<Vec<f32>>>()
 }
+
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+/// Audio filters applied to the samples before extracting its features, as the detector does with the live audio.
+pub(crate) struct SampleFilterSettings {
+    #[clap(short = 'g', long)]
+    /// Enables a gain-normalizer audio filter.
+    pub gain_normalizer: bool,
+    #[clap(long, default_value_t = 0.1)]
+    /// Min gain applied by the gain-normalizer filter.
+    pub min_gain: f32,
+    #[clap(long, default_value_t = 1.)]
+    /// Max gain applied by the gain-normalizer filter.
+    pub max_gain: f32,
+    #[clap(long)]
+    /// Set the rms level reference used by the gain normalizer filter.
+    /// If unset the wakeword rms level is used.
+    pub gain_ref: Option<f32>,
+    #[clap(short, long)]
+    /// Enables a band-pass audio filter.
+    pub band_pass: bool,
+    #[clap(long, default_value_t = 80.)]
+    /// Band-pass audio filter low cutoff.
+    pub low_cutoff: f32,
+    #[clap(long, default_value_t = 400.)]
+    /// Band-pass audio filter high cutoff.
+    pub high_cutoff: f32,
+}
+impl SampleFilterSettings {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.gain_normalizer || self.band_pass
+    }
+}
+
+/// Returns the rms level of a wav sample as computed when building a wakeword, the median of its frames levels.
+pub(crate) fn get_sample_rms_level(buffer: &[u8]) -> Result<f32, String> {
+    Ok(get_median_rms_level(
+        encode_wav(buffer)?
+            .iter()
+            .map(|chunk| GainNormalizerFilter::get_rms_level(chunk)),
+    ))
+}
+
+/// Returns the median of the samples rms levels, the level the library keeps when building a reference from files.
+pub(crate) fn get_median_rms_level(rms_levels: impl Iterator<Item = f32>) -> f32 {
+    let mut rms_levels = rms_levels.collect::<Vec<_>>();
+    if rms_levels.is_empty() {
+        return 0.;
+    }
+    rms_levels.sort_by(|a, b| a.total_cmp(b));
+    rms_levels[rms_levels.len() / 2]
+}
+
+/// Applies the filters to a wav sample, returning it as a float wav file at the detector sample rate.
+pub(crate) fn filter_sample(
+    buffer: &[u8],
+    settings: &SampleFilterSettings,
+    rms_level_ref: f32,
+) -> Result<Vec<u8>, String> {
+    let chunks = encode_wav(buffer)?;
+    let mut gain_filter = GainNormalizerFilter::new(
+        settings.min_gain,
+        settings.max_gain,
+        Some(settings.gain_ref.unwrap_or(rms_level_ref)),
+    );
+    // the detector averages the rms level over a third of the wakeword mfcc frames, about a chunk per frame
+    gain_filter.set_rms_level_ref(rms_level_ref, chunks.len());
+    let mut bandpass_filter = BandPassFilter::new(
+        DETECTOR_INTERNAL_SAMPLE_RATE as f32,
+        settings.low_cutoff,
+        settings.high_cutoff,
+    );
+    let mut output = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut output, INTERNAL_SPEC).map_err(|err| err.to_string())?;
+    for mut chunk in chunks {
+        if settings.gain_normalizer {
+            let rms_level = GainNormalizerFilter::get_rms_level(&chunk);
+            gain_filter.filter(&mut chunk, rms_level);
+        }
+        if settings.band_pass {
+            bandpass_filter.filter(&mut chunk);
+        }
+        for sample in chunk {
+            writer.write_sample(sample).map_err(|err| err.to_string())?;
+        }
+    }
+    writer.finalize().map_err(|err| err.to_string())?;
+    Ok(output.into_inner())
+}
+/// Returns the wav sample encoded as the detector input, in chunks of a mfcc frame length.
+fn encode_wav(buffer: &[u8]) -> Result<Vec<Vec<f32>>, String> {
+    let mut wav_reader = WavReader::new(buffer).map_err(|err| err.to_string())?;
+    let wav_spec: AudioFmt = wav_reader.spec().try_into()?;
+    let mut encoder = AudioEncoder::new(
+        &wav_spec,
+        MFCCS_EXTRACTOR_FRAME_LENGTH_MS,
+        DETECTOR_INTERNAL_SAMPLE_RATE,
+    )?;
+    Ok(match wav_spec.sample_format {
+        SampleFormat::I8 => get_encoded_chucks::<i8, _>(&mut wav_reader, &mut encoder),
+        SampleFormat::I16 => get_encoded_chucks::<i16, _>(&mut wav_reader, &mut encoder),
+        SampleFormat::I32 => get_encoded_chucks::<i32, _>(&mut wav_reader, &mut encoder),
+        SampleFormat::F32 => get_encoded_chucks::<f32, _>(&mut wav_reader, &mut encoder),
+    })
+}
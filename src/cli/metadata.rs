@@ -0,0 +1,97 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    filter::SampleFilterSettings,
+    provenance::{get_iso_timestamp, get_sidecar_path},
+};
+
+/// Settings used to create a wakeword file, written as a json file next to it.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct WakewordMetadata {
+    /// Audio filters applied to the samples, the detector should enable the same ones.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filters: Option<SampleFilterSettings>,
+    /// Seed used to train the model.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Rms level of each reference sample before filtering, the reference keeps the median one.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sample_rms_levels: BTreeMap<String, f32>,
+    /// Command line arguments used to create the wakeword file.
+    #[serde(default)]
+    arguments: Vec<String>,
+    #[serde(default)]
+    timestamp: String,
+    #[serde(default)]
+    cli_version: String,
+}
+impl WakewordMetadata {
+    pub(crate) fn new() -> WakewordMetadata {
+        WakewordMetadata {
+            filters: None,
+            seed: None,
+            sample_rms_levels: BTreeMap::new(),
+            arguments: std::env::args().skip(1).collect(),
+            timestamp: get_iso_timestamp(),
+            cli_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+    /// Reads the metadata stored next to the wakeword file, if any.
+    pub(crate) fn load(wakeword_path: &str) -> Result<Option<WakewordMetadata>, String> {
+        let metadata_path = get_sidecar_path(wakeword_path);
+        if !metadata_path.exists() {
+            return Ok(None);
+        }
+        let reader = BufReader::new(File::open(&metadata_path).map_err(|err| err.to_string())?);
+        serde_json::from_reader(reader)
+            .map(Some)
+            .map_err(|err| format!("{}: {}", metadata_path.display(), err))
+    }
+    /// Writes the metadata next to the wakeword file.
+    pub(crate) fn write(&self, wakeword_path: &str) -> Result<(), String> {
+        let file = File::create(get_sidecar_path(wakeword_path)).map_err(|err| err.to_string())?;
+        serde_json::to_writer_pretty(file, self).map_err(|err| err.to_string())
+    }
+}
+/// Returns the directory next to the wakeword file where the samples used to build it are kept.
+pub(crate) fn get_samples_dir(wakeword_path: &str) -> PathBuf {
+    Path::new(wakeword_path).with_extension("samples")
+}
+/// Writes the samples, as used to extract the reference features, into the wakeword samples directory.
+pub(crate) fn write_reference_samples<'a>(
+    wakeword_path: &str,
+    samples: impl Iterator<Item = (&'a String, &'a Vec<u8>)>,
+) -> Result<(), String> {
+    let samples_dir = get_samples_dir(wakeword_path);
+    std::fs::create_dir_all(&samples_dir).map_err(|err| err.to_string())?;
+    for (name, buffer) in samples {
+        std::fs::write(samples_dir.join(name), buffer).map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+/// Reads the samples from the wakeword samples directory, returns none if any of them is missing.
+pub(crate) fn read_reference_samples<'a>(
+    wakeword_path: &str,
+    sample_names: impl Iterator<Item = &'a String>,
+) -> Result<Option<HashMap<String, Vec<u8>>>, String> {
+    let samples_dir = get_samples_dir(wakeword_path);
+    let mut samples = HashMap::new();
+    for name in sample_names {
+        let path = samples_dir.join(name);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        samples.insert(
+            name.clone(),
+            std::fs::read(path).map_err(|err| err.to_string())?,
+        );
+    }
+    Ok(Some(samples))
+}
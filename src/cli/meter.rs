@@ -0,0 +1,125 @@
+use std::{
+    io::{IsTerminal, Write},
+    time::{Duration, Instant},
+};
+
+use super::audio::{get_rms_from_square_sum, to_dbfs, CLIPPING_LEVEL};
+
+/// Takes with a lower peak level, in dBFS, are reported as too quiet.
+const QUIET_PEAK_DB: f32 = -30.;
+/// Lower level displayed by the meter bar, in dBFS.
+const METER_FLOOR_DB: f32 = -60.;
+const METER_WIDTH: usize = 30;
+const METER_REFRESH: Duration = Duration::from_millis(100);
+
+/// Measures the peak and rms levels of the recorded audio, optionally displaying them live on stderr.
+pub(crate) struct LevelMeter {
+    /// Samples per second, all channels included.
+    samples_per_sec: usize,
+    live: bool,
+    peak: f32,
+    square_sum: f64,
+    samples: usize,
+    clipped_samples: usize,
+    window_peak: f32,
+    window_square_sum: f64,
+    window_samples: usize,
+    window_clipped: bool,
+    last_refresh: Instant,
+}
+impl LevelMeter {
+    pub(crate) fn new(sample_rate: u32, channels: u16, live: bool) -> LevelMeter {
+        LevelMeter {
+            samples_per_sec: sample_rate as usize * channels as usize,
+            live: live && std::io::stderr().is_terminal(),
+            peak: 0.,
+            square_sum: 0.,
+            samples: 0,
+            clipped_samples: 0,
+            window_peak: 0.,
+            window_square_sum: 0.,
+            window_samples: 0,
+            window_clipped: false,
+            last_refresh: Instant::now(),
+        }
+    }
+    pub(crate) fn add_samples(&mut self, samples: &[f32]) {
+        for sample in samples {
+            let level = sample.abs();
+            let square = (*sample as f64).powi(2);
+            self.peak = self.peak.max(level);
+            self.window_peak = self.window_peak.max(level);
+            self.square_sum += square;
+            self.window_square_sum += square;
+            if level >= CLIPPING_LEVEL {
+                self.clipped_samples += 1;
+                self.window_clipped = true;
+            }
+        }
+        self.samples += samples.len();
+        self.window_samples += samples.len();
+        if self.live && self.last_refresh.elapsed() >= METER_REFRESH {
+            self.print_live_level();
+        }
+    }
+    /// Removes the live meter line, so other messages can be printed.
+    pub(crate) fn clear(&self) {
+        if self.live {
+            eprint!("\r{:width$}\r", "", width = METER_WIDTH + 50);
+        }
+    }
+    pub(crate) fn print_summary(&self) {
+        self.clear();
+        let peak_db = to_dbfs(self.peak);
+        println!(
+            "Duration {:.2}s, peak {:.1} dBFS, rms {:.1} dBFS, {} clipped samples.",
+            self.samples as f32 / self.samples_per_sec as f32,
+            peak_db,
+            to_dbfs(get_rms_from_square_sum(self.square_sum, self.samples)),
+            self.clipped_samples
+        );
+        if self.clipped_samples != 0 {
+            println!("Warning: audio clipped, lower the gain or move away from the microphone.");
+        }
+        if peak_db < QUIET_PEAK_DB {
+            println!("Warning: audio too quiet, increase the gain or speak louder.");
+        }
+    }
+    fn print_live_level(&mut self) {
+        let rms_db = to_dbfs(get_rms_from_square_sum(
+            self.window_square_sum,
+            self.window_samples,
+        ));
+        let peak_db = to_dbfs(self.window_peak);
+        let get_position = |level_db: f32| {
+            (((level_db - METER_FLOOR_DB) / -METER_FLOOR_DB).clamp(0., 1.) * METER_WIDTH as f32)
+                as usize
+        };
+        let rms_position = get_position(rms_db);
+        let peak_position = get_position(peak_db).min(METER_WIDTH - 1);
+        let bar = (0..METER_WIDTH)
+            .map(|index| match index {
+                index if index == peak_position => '|',
+                index if index < rms_position => '#',
+                _ => '.',
+            })
+            .collect::<String>();
+        eprint!(
+            "\r[{}] peak {:6.1} dBFS, rms {:6.1} dBFS {}",
+            bar,
+            peak_db,
+            rms_db,
+            if self.window_clipped {
+                "CLIPPING"
+            } else {
+                "        "
+            }
+        );
+        std::io::stderr().flush().ok();
+        self.window_peak = 0.;
+        self.window_square_sum = 0.;
+        self.window_samples = 0;
+        self.window_clipped = false;
+        self.last_refresh = Instant::now();
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use rustpotter::{TensorData, WakewordModel, WakewordRef, WakewordRefBuildFromBuffers};
+use serde::Serialize;
+
+use super::split::get_label;
+
+/// Test record features, computed once to evaluate the model on each test epoch.
+pub(crate) struct TestRecords {
+    records: Vec<TestRecord>,
+}
+struct TestRecord {
+    name: String,
+    label: String,
+    features: Vec<f32>,
+}
+/// Weight and bias values of a model linear layer, the weight dims are [output, input].
+struct LinearLayer {
+    weight: Vec<f32>,
+    bias: Vec<f32>,
+}
+/// Predictions of the model on the test records.
+pub(crate) struct TestResult {
+    labels: Vec<String>,
+    /// Number of records by expected and predicted label index.
+    matrix: Vec<Vec<usize>>,
+    /// Name, expected label and predicted label of the wrong predictions.
+    misclassified: Vec<(String, String, String)>,
+}
+#[derive(Serialize)]
+struct LabelMetrics {
+    records: usize,
+    precision: Option<f32>,
+    recall: Option<f32>,
+}
+#[derive(Serialize)]
+struct EpochMetrics {
+    epoch: usize,
+    loss: f32,
+    accuracy: f32,
+    learning_rate: f64,
+    labels: BTreeMap<String, LabelMetrics>,
+}
+/// Writes a json line with the test metrics of each test epoch.
+pub(crate) struct MetricsLog {
+    writer: BufWriter<File>,
+}
+
+impl TestRecords {
+    /// Extracts the features of the test records, the same way the training does.
+    pub(crate) fn new(
+        test_samples: &HashMap<String, Vec<u8>>,
+        mfcc_size: u16,
+    ) -> Result<TestRecords, String> {
+        let mut names = test_samples.keys().collect::<Vec<_>>();
+        names.sort();
+        let mut records = Vec::new();
+        for name in names {
+            let wakeword = WakewordRef::new_from_sample_buffers(
+                name.clone(),
+                None,
+                None,
+                HashMap::from([(name.clone(), test_samples[name].clone())]),
+                mfcc_size,
+            )?;
+            let features = wakeword
+                .samples_features
+                .into_values()
+                .flatten()
+                .flatten()
+                .collect();
+            records.push(TestRecord {
+                name: name.clone(),
+                label: get_label(name),
+                features,
+            });
+        }
+        Ok(TestRecords { records })
+    }
+    /// Runs the model on the test records.
+    pub(crate) fn evaluate(&self, model: &WakewordModel) -> Result<TestResult, String> {
+        let layers = get_layers(model)?;
+        let input_len = model.train_size * model.mfcc_size as usize;
+        let mut result = TestResult {
+            labels: model.labels.clone(),
+            matrix: vec![vec![0; model.labels.len()]; model.labels.len()],
+            misclassified: Vec::new(),
+        };
+        for record in &self.records {
+            let expected = model
+                .labels
+                .iter()
+                .position(|label| *label == record.label)
+                .ok_or_else(|| format!("Unknown label '{}' in test records", record.label))?;
+            // padded or truncated to the training duration
+            let mut values = record.features.clone();
+            values.resize(input_len, 0.);
+            for (index, layer) in layers.iter().enumerate() {
+                values = layer.forward(&values);
+                if index + 1 < layers.len() {
+                    values.iter_mut().for_each(|value| *value = value.max(0.));
+                }
+            }
+            let predicted = values
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(index, _)| index)
+                .unwrap_or_default();
+            result.matrix[expected][predicted] += 1;
+            if predicted != expected {
+                result.misclassified.push((
+                    record.name.clone(),
+                    record.label.clone(),
+                    model.labels[predicted].clone(),
+                ));
+            }
+        }
+        Ok(result)
+    }
+}
+impl TestResult {
+    /// Returns the percentage of records predicted correctly.
+    pub(crate) fn get_accuracy(&self) -> f32 {
+        let total = self.matrix.iter().flatten().sum::<usize>();
+        let correct = (0..self.labels.len())
+            .map(|index| self.matrix[index][index])
+            .sum::<usize>();
+        100. * correct as f32 / total.max(1) as f32
+    }
+    fn get_label_metrics(&self, index: usize) -> LabelMetrics {
+        let correct = self.matrix[index][index];
+        let records = self.matrix[index].iter().sum::<usize>();
+        let predictions = self.matrix.iter().map(|row| row[index]).sum::<usize>();
+        LabelMetrics {
+            records,
+            precision: (predictions != 0).then(|| correct as f32 / predictions as f32),
+            recall: (records != 0).then(|| correct as f32 / records as f32),
+        }
+    }
+    /// Prints the confusion matrix, the metrics of each label and the misclassified records.
+    pub(crate) fn print(&self) {
+        println!("Confusion matrix on the test records, rows are the expected labels and columns the predicted ones:");
+        let width = self
+            .labels
+            .iter()
+            .map(|label| label.len())
+            .max()
+            .unwrap_or_default()
+            .max(6);
+        print!("{:width$}", "", width = width);
+        for label in &self.labels {
+            print!(" {:>width$}", label, width = width);
+        }
+        println!();
+        for (label, row) in self.labels.iter().zip(&self.matrix) {
+            print!("{:width$}", label, width = width);
+            for count in row {
+                print!(" {:>width$}", count, width = width);
+            }
+            println!();
+        }
+        for (index, label) in self.labels.iter().enumerate() {
+            let metrics = self.get_label_metrics(index);
+            println!(
+                "{}: {} records, precision {}, recall {}",
+                label,
+                metrics.records,
+                format_ratio(metrics.precision),
+                format_ratio(metrics.recall)
+            );
+        }
+        if self.misclassified.is_empty() {
+            println!("No misclassified test records.");
+        } else {
+            println!("Misclassified test records:");
+            for (name, label, predicted) in &self.misclassified {
+                println!("{}: {} predicted as {}", name, label, predicted);
+            }
+        }
+    }
+}
+impl MetricsLog {
+    pub(crate) fn new(path: &str) -> Result<MetricsLog, String> {
+        Ok(MetricsLog {
+            writer: BufWriter::new(File::create(path).map_err(|err| err.to_string())?),
+        })
+    }
+    pub(crate) fn write(
+        &mut self,
+        epoch: usize,
+        loss: f32,
+        learning_rate: f64,
+        result: &TestResult,
+    ) -> Result<(), String> {
+        let metrics = EpochMetrics {
+            epoch,
+            loss,
+            accuracy: result.get_accuracy(),
+            learning_rate,
+            labels: result
+                .labels
+                .iter()
+                .enumerate()
+                .map(|(index, label)| (label.clone(), result.get_label_metrics(index)))
+                .collect(),
+        };
+        serde_json::to_writer(&mut self.writer, &metrics).map_err(|err| err.to_string())?;
+        writeln!(self.writer).map_err(|err| err.to_string())?;
+        self.writer.flush().map_err(|err| err.to_string())
+    }
+}
+/// Returns the linear layers of the model, in order.
+fn get_layers(model: &WakewordModel) -> Result<Vec<LinearLayer>, String> {
+    let mut layer_names = model
+        .weights
+        .keys()
+        .filter_map(|name| name.strip_suffix(".weight"))
+        .collect::<Vec<_>>();
+    layer_names.sort();
+    layer_names
+        .into_iter()
+        .map(|layer| {
+            let bias = model
+                .weights
+                .get(&(layer.to_string() + ".bias"))
+                .ok_or_else(|| format!("Missing {} bias", layer))?;
+            Ok(LinearLayer {
+                weight: to_f32(&model.weights[&(layer.to_string() + ".weight")]),
+                bias: to_f32(bias),
+            })
+        })
+        .collect()
+}
+impl LinearLayer {
+    /// Returns the weight times the input plus the bias.
+    fn forward(&self, input: &[f32]) -> Vec<f32> {
+        self.weight
+            .chunks(input.len())
+            .zip(&self.bias)
+            .map(|(row, bias)| row.iter().zip(input).map(|(w, x)| w * x).sum::<f32>() + bias)
+            .collect()
+    }
+}
+fn to_f32(tensor: &TensorData) -> Vec<f32> {
+    tensor
+        .bytes
+        .chunks_exact(4)
+        .map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+fn format_ratio(ratio: Option<f32>) -> String {
+    ratio.map_or("-".to_string(), |ratio| format!("{:.2}%", ratio * 100.))
+}
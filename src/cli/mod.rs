@@ -1,19 +1,45 @@
 use clap::{Parser, Subcommand};
+mod analyze;
+mod audio;
+mod augment;
+mod bench;
+mod benchmark_fa;
 mod build;
+mod calibrate;
+mod converter;
 mod devices;
+mod evaluate;
 mod filter;
+mod metadata;
+mod meter;
+mod metrics;
+mod provenance;
+mod quality;
 mod record;
+mod record_session;
+mod reference;
+mod segments;
+mod split;
 mod spot;
 mod test;
+mod trace;
 mod train;
+mod verify;
+mod weights;
 use self::{
+    bench::{bench, BenchCommand},
+    benchmark_fa::{benchmark_fa, BenchmarkFaCommand},
     build::{build_ref, BuildCommand},
     devices::{devices, DevicesCommand},
+    evaluate::{evaluate, EvaluateCommand},
     filter::{filter, FilterCommand},
     record::{record, RecordCommand},
+    record_session::{record_session, RecordSessionCommand},
+    reference::{reference, RefCommand},
     spot::{spot, SpotCommand},
     test::{test, TestCommand},
     train::{train, TrainCommand},
+    verify::{verify, VerifyCommand},
 };
 
 #[derive(Parser, Debug)]
@@ -45,7 +71,7 @@ enum Command {
     /// The will train a basic classification neural network for the available labels, that the tool can use emit detections when a label other
     /// than "none" is predicted.
     ///
-    /// The weight initialization is not fixed and can produce different results per execution but the
+    /// The weight initialization is random unless a seed is provided, the seed used is stored in the model metadata.
     ///
     /// Tested with a training set of 155 affirmative samples and 1355 noise/ambient samples over a test set of 108 samples.
     /// I obtain a round 96% of accuracy using the different model types, and all work nice in real live,
@@ -53,6 +79,10 @@ enum Command {
     /// but other than that all seems to be reliable.
     ///
     Train(TrainCommand),
+    /// Add, remove or list the samples of a wakeword reference
+    ///
+    /// The reference keeps its name, thresholds and mfcc size, and its averaged features and rms level are recomputed from the new sample set.
+    Ref(RefCommand),
     /// List available audio devices and configurations
     ///
     /// Useful in order to know how to configure the input and format
@@ -62,22 +92,63 @@ enum Command {
     Filter(FilterCommand),
     /// Record wav audio file
     Record(RecordCommand),
+    /// Record a set of takes for a training dataset
+    ///
+    /// Prompts with a countdown before each take, records it with a fixed duration or until a silence,
+    /// and asks to keep or redo it, warning about clipped or too quiet takes.
+    ///
+    /// Kept takes are named with the "[label]" convention and split between the "train" and "test" subdirectories.
+    RecordSession(RecordSessionCommand),
     /// Spot wakewords in real time
     Spot(SpotCommand),
     /// Spot wakewords against a wav file  
     Test(TestCommand),
+    /// Verify the detections on a list of wav files
+    ///
+    /// Reads a yaml or toml manifest listing the records, the models to load, the detector settings
+    /// and the expected detections on each record (name, count and approximated offsets).
+    ///
+    /// Exits with a non-zero code when any record does not match its expectations.
+    Verify(VerifyCommand),
+    /// Measure the false alarm rate on wav files without wakewords
+    ///
+    /// Streams every wav file on the directory through the detector and reports the false alarms per hour,
+    /// overall and per file.
+    ///
+    /// Each false alarm can be exported as a short clip, ready to be used as "[none]" training data.
+    BenchmarkFa(BenchmarkFaCommand),
+    /// Measure the detection rate on wav files containing the wakewords
+    ///
+    /// Each wav file on the directory counts as detected when the detector emits any detection on it.
+    ///
+    /// Optionally mixes random segments of noise records into the samples at the provided signal to noise ratios,
+    /// reporting the detection rate of each level.
+    Evaluate(EvaluateCommand),
+    /// Measure the cpu time and memory needed to process audio with each wakeword file
+    ///
+    /// Feeds white noise, or a wav record, to a detector loaded with each file for a fixed audio duration,
+    /// reporting the mean and p99 time per frame, the real time factor, the peak memory and the file size.
+    ///
+    /// Use the json output to compare the results across devices.
+    Bench(BenchCommand),
 }
 
 pub(crate) fn run_cli() {
     let cli = Cli::parse();
     match cli.command.unwrap() {
+        Command::Bench(command) => bench(command),
+        Command::BenchmarkFa(command) => benchmark_fa(command),
         Command::Build(command) => build_ref(command),
         Command::Devices(command) => devices(command),
+        Command::Evaluate(command) => evaluate(command),
         Command::Filter(command) => filter(command),
         Command::Record(command) => record(command),
+        Command::Ref(command) => reference(command),
+        Command::RecordSession(command) => record_session(command),
         Command::Spot(command) => spot(command),
         Command::Test(command) => test(command),
         Command::Train(command) => train(command),
+        Command::Verify(command) => verify(command),
     }
     .expect("Command failed");
 }
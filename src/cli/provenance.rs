@@ -0,0 +1,230 @@
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use clap::Args;
+use cpal::traits::DeviceTrait;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+/// Sidecar fields that can be used to filter the samples.
+const FILTER_FIELDS: [&str; 11] = [
+    "host",
+    "device",
+    "sample_rate",
+    "channels",
+    "sample_format",
+    "gain",
+    "timestamp",
+    "speaker",
+    "room",
+    "tag",
+    "cli_version",
+];
+
+#[derive(Args, Debug)]
+/// Information about the recording conditions stored next to the records.
+pub(crate) struct ProvenanceArgs {
+    #[clap(long)]
+    /// Speaker name stored in the records metadata.
+    speaker: Option<String>,
+    #[clap(long)]
+    /// Room name stored in the records metadata.
+    room: Option<String>,
+    #[clap(long)]
+    /// Free tag stored in the records metadata.
+    tag: Option<String>,
+}
+
+/// Recording conditions of a wav record, written as a json file next to it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct Provenance {
+    host: String,
+    device: String,
+    sample_rate: u32,
+    channels: u16,
+    sample_format: String,
+    gain: f32,
+    timestamp: String,
+    speaker: Option<String>,
+    room: Option<String>,
+    tag: Option<String>,
+    cli_version: String,
+}
+impl Provenance {
+    pub(crate) fn new(
+        host_name: &str,
+        device: &cpal::Device,
+        device_config: &cpal::SupportedStreamConfig,
+        gain: f32,
+        args: &ProvenanceArgs,
+    ) -> Provenance {
+        Provenance {
+            host: host_name.to_string(),
+            device: device.name().unwrap_or_default(),
+            sample_rate: device_config.sample_rate().0,
+            channels: device_config.channels(),
+            sample_format: device_config.sample_format().to_string(),
+            gain,
+            timestamp: String::new(),
+            speaker: args.speaker.clone(),
+            room: args.room.clone(),
+            tag: args.tag.clone(),
+            cli_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+    /// Sets the gain applied to the next records.
+    pub(crate) fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+    /// Writes the sidecar of the wav record, timestamped with the current time.
+    pub(crate) fn write_sidecar(&self, wav_path: &str) -> Result<(), String> {
+        let provenance = Provenance {
+            timestamp: get_iso_timestamp(),
+            ..self.clone()
+        };
+        let file = File::create(get_sidecar_path(wav_path)).map_err(|err| err.to_string())?;
+        serde_json::to_writer_pretty(file, &provenance).map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Args, Debug, Default)]
+/// Options to select samples by the metadata stored next to them.
+pub(crate) struct ProvenanceFilter {
+    #[clap(long = "filter", value_parser = parse_filter)]
+    /// Only use the samples whose metadata field has this value ("speaker=alice", "room=kitchen"), can be repeated.
+    /// Samples without metadata are excluded when a filter is set.
+    filters: Vec<(String, String)>,
+}
+impl ProvenanceFilter {
+    /// Returns whether the sample metadata matches all the filters.
+    pub(crate) fn matches(&self, wav_path: &str) -> Result<bool, String> {
+        if self.filters.is_empty() {
+            return Ok(true);
+        }
+        let sidecar_path = get_sidecar_path(wav_path);
+        if !sidecar_path.exists() {
+            return Ok(false);
+        }
+        let reader = BufReader::new(File::open(&sidecar_path).map_err(|err| err.to_string())?);
+        let metadata: serde_json::Value = serde_json::from_reader(reader)
+            .map_err(|err| format!("{}: {}", sidecar_path.display(), err))?;
+        Ok(self
+            .filters
+            .iter()
+            .all(|(field, value)| match metadata.get(field) {
+                Some(serde_json::Value::String(field_value)) => field_value == value,
+                Some(serde_json::Value::Number(field_value)) => {
+                    value.parse::<f64>().ok() == field_value.as_f64()
+                }
+                _ => false,
+            }))
+    }
+    /// Returns the sample paths whose metadata matches the filters.
+    pub(crate) fn filter_paths(&self, wav_paths: Vec<String>) -> Result<Vec<String>, String> {
+        let mut filtered_paths = Vec::with_capacity(wav_paths.len());
+        for wav_path in wav_paths {
+            if self.matches(&wav_path)? {
+                filtered_paths.push(wav_path);
+            }
+        }
+        Ok(filtered_paths)
+    }
+}
+fn parse_filter(value: &str) -> Result<(String, String), String> {
+    let (field, field_value) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid filter '{}', expected 'field=value'", value))?;
+    if !FILTER_FIELDS.contains(&field) {
+        return Err(format!(
+            "Unknown metadata field '{}', available fields are: {}",
+            field,
+            FILTER_FIELDS.join(", ")
+        ));
+    }
+    Ok((field.to_string(), field_value.to_string()))
+}
+/// Returns the sidecar path of a wav record, its path with the "json" extension.
+pub(crate) fn get_sidecar_path(wav_path: &str) -> PathBuf {
+    Path::new(wav_path).with_extension("json")
+}
+pub(crate) fn get_iso_timestamp() -> String {
+    let dt: OffsetDateTime = SystemTime::now().into();
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        dt.year(),
+        dt.month() as u8,
+        dt.day(),
+        dt.hour(),
+        dt.minute(),
+        dt.second()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_filter_checks_the_field() {
+        assert_eq!(
+            parse_filter("speaker=alice"),
+            Ok(("speaker".to_string(), "alice".to_string()))
+        );
+        assert_eq!(
+            parse_filter("tag=a=b"),
+            Ok(("tag".to_string(), "a=b".to_string()))
+        );
+        assert_eq!(
+            parse_filter("room="),
+            Ok(("room".to_string(), String::new()))
+        );
+        assert!(parse_filter("speaker").is_err());
+        assert!(parse_filter("unknown=value").is_err());
+    }
+
+    #[test]
+    fn filter_matches_the_sidecar_fields() {
+        let dir =
+            std::env::temp_dir().join(format!("rustpotter-cli-provenance-{}", std::process::id()));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let wav_path = dir.join("take.wav").to_string_lossy().to_string();
+        let other_wav_path = dir.join("other.wav").to_string_lossy().to_string();
+        std::fs::write(
+            get_sidecar_path(&wav_path),
+            r#"{"speaker": "alice", "sample_rate": 16000, "room": null}"#,
+        )
+        .unwrap();
+        let get_filter = |filters: &[&str]| ProvenanceFilter {
+            filters: filters
+                .iter()
+                .map(|filter| parse_filter(filter).unwrap())
+                .collect(),
+        };
+        assert_eq!(get_filter(&[]).matches(&other_wav_path), Ok(true));
+        assert_eq!(get_filter(&["speaker=alice"]).matches(&wav_path), Ok(true));
+        assert_eq!(
+            get_filter(&["speaker=alice", "sample_rate=16000"]).matches(&wav_path),
+            Ok(true)
+        );
+        assert_eq!(get_filter(&["speaker=bob"]).matches(&wav_path), Ok(false));
+        assert_eq!(
+            get_filter(&["speaker=alice", "sample_rate=44100"]).matches(&wav_path),
+            Ok(false)
+        );
+        assert_eq!(get_filter(&["room="]).matches(&wav_path), Ok(false));
+        assert_eq!(
+            get_filter(&["speaker=alice"]).matches(&other_wav_path),
+            Ok(false)
+        );
+        assert_eq!(
+            get_filter(&["speaker=alice"]).filter_paths(vec![wav_path.clone(), other_wav_path]),
+            Ok(vec![wav_path])
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
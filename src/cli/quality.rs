@@ -0,0 +1,221 @@
+use std::{fmt::Display, io::Cursor};
+
+use clap::Args;
+use hound::{WavSpec, WavWriter};
+
+use super::audio::{get_rms, to_dbfs, CLIPPING_LEVEL};
+
+/// Duration of the windows used to measure the sample level.
+const LEVEL_WINDOW_MS: usize = 30;
+/// Percentage of clipped samples over which the sample is rejected.
+const MAX_CLIPPED_PERCENT: f32 = 0.1;
+/// Leading or trailing silence over this duration is reported.
+const MAX_SILENCE_MS: usize = 500;
+/// Samples with a lower rms level, in dBFS, are reported as too quiet.
+const QUIET_RMS_DB: f32 = -35.;
+/// Milliseconds of silence kept at both ends when trimming.
+const TRIM_MARGIN_MS: usize = 100;
+
+#[derive(Args, Debug)]
+/// Options to validate the samples before using them.
+pub(crate) struct QualitySettings {
+    #[clap(long, default_value_t = 300)]
+    /// Min sample duration in milliseconds.
+    min_duration_ms: usize,
+    #[clap(long, default_value_t = 3000)]
+    /// Max sample duration in milliseconds.
+    max_duration_ms: usize,
+    #[clap(long, default_value_t = -40., allow_hyphen_values = true)]
+    /// Level in dBFS under which the audio is considered silence.
+    silence_threshold_db: f32,
+    #[clap(long)]
+    /// Use the samples even if some of them have errors.
+    pub force: bool,
+    #[clap(long)]
+    /// Remove the leading and trailing silence of the samples before extracting its features.
+    pub trim: bool,
+}
+
+/// Result of the quality checks of a sample.
+pub(crate) struct SampleReport {
+    pub path: String,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    spec: WavSpec,
+    duration_ms: usize,
+    leading_silence_ms: usize,
+    trailing_silence_ms: usize,
+    peak_db: f32,
+    rms_db: f32,
+    /// Range of samples not considered silence, with the trim margin.
+    voiced_range: std::ops::Range<usize>,
+}
+impl Display for SampleReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {}, {:.2}s, silence {:.2}s/{:.2}s, peak {:.1} dBFS, rms {:.1} dBFS",
+            self.path,
+            format_spec(&self.spec),
+            self.duration_ms as f32 / 1000.,
+            self.leading_silence_ms as f32 / 1000.,
+            self.trailing_silence_ms as f32 / 1000.,
+            self.peak_db,
+            self.rms_db
+        )?;
+        for error in &self.errors {
+            write!(f, "\n  error: {}", error)?;
+        }
+        for warning in &self.warnings {
+            write!(f, "\n  warning: {}", warning)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the sample duration, silence, clipping and level, the samples are the file ones as float.
+pub(crate) fn check_sample(
+    path: &str,
+    spec: &WavSpec,
+    samples: &[f32],
+    settings: &QualitySettings,
+) -> SampleReport {
+    let channels = spec.channels as usize;
+    let samples_per_ms = spec.sample_rate as usize * channels / 1000;
+    let window_size = (LEVEL_WINDOW_MS * samples_per_ms).max(channels);
+    let threshold = 10_f32.powf(settings.silence_threshold_db / 20.);
+    let voiced_windows = samples
+        .chunks(window_size)
+        .map(|window| get_rms(window) >= threshold)
+        .collect::<Vec<_>>();
+    let first_voiced = voiced_windows.iter().position(|voiced| *voiced);
+    let last_voiced = voiced_windows.iter().rposition(|voiced| *voiced);
+    let (voiced_start, voiced_end) = match (first_voiced, last_voiced) {
+        (Some(first), Some(last)) => (
+            first * window_size,
+            ((last + 1) * window_size).min(samples.len()),
+        ),
+        _ => (samples.len(), samples.len()),
+    };
+    let margin = TRIM_MARGIN_MS * samples_per_ms;
+    let clipped_samples = samples
+        .iter()
+        .filter(|sample| sample.abs() >= CLIPPING_LEVEL)
+        .count();
+    let mut report = SampleReport {
+        path: path.to_string(),
+        errors: Vec::new(),
+        warnings: Vec::new(),
+        spec: *spec,
+        duration_ms: samples.len() / samples_per_ms.max(1),
+        leading_silence_ms: voiced_start / samples_per_ms.max(1),
+        trailing_silence_ms: (samples.len() - voiced_end) / samples_per_ms.max(1),
+        peak_db: to_dbfs(
+            samples
+                .iter()
+                .fold(0., |peak, sample| sample.abs().max(peak)),
+        ),
+        rms_db: to_dbfs(get_rms(&samples[voiced_start.min(voiced_end)..voiced_end])),
+        voiced_range: if first_voiced.is_some() {
+            voiced_start.saturating_sub(margin) / channels * channels
+                ..(voiced_end + margin).min(samples.len()) / channels * channels
+        } else {
+            0..samples.len()
+        },
+    };
+    if first_voiced.is_none() {
+        report.errors.push(format!(
+            "no audio over {:.1} dBFS",
+            settings.silence_threshold_db
+        ));
+        return report;
+    }
+    // trimmed samples are checked by the duration they will have
+    let duration_ms = if settings.trim {
+        report.voiced_range.len() / samples_per_ms.max(1)
+    } else {
+        report.duration_ms
+    };
+    if duration_ms < settings.min_duration_ms {
+        report
+            .errors
+            .push(format!("shorter than {}ms", settings.min_duration_ms));
+    }
+    if duration_ms > settings.max_duration_ms {
+        report
+            .errors
+            .push(format!("longer than {}ms", settings.max_duration_ms));
+    }
+    if !settings.trim
+        && (report.leading_silence_ms > MAX_SILENCE_MS
+            || report.trailing_silence_ms > MAX_SILENCE_MS)
+    {
+        report
+            .warnings
+            .push("long silence at the ends, consider using --trim".to_string());
+    }
+    let clipped_percent = clipped_samples as f32 * 100. / samples.len() as f32;
+    if clipped_percent > MAX_CLIPPED_PERCENT {
+        report.errors.push(format!(
+            "{} clipped samples ({:.2}%)",
+            clipped_samples, clipped_percent
+        ));
+    } else if clipped_samples != 0 {
+        report
+            .warnings
+            .push(format!("{} clipped samples", clipped_samples));
+    }
+    if report.rms_db < QUIET_RMS_DB {
+        report.warnings.push("too quiet".to_string());
+    }
+    report
+}
+
+/// Adds an error to the reports whose sample format differs from the most common one.
+pub(crate) fn check_formats(reports: &mut [SampleReport]) {
+    let specs = reports.iter().map(|report| report.spec).collect::<Vec<_>>();
+    let Some(common_spec) = specs
+        .iter()
+        .max_by_key(|spec| specs.iter().filter(|other| other == spec).count())
+    else {
+        return;
+    };
+    for report in reports.iter_mut() {
+        if report.spec != *common_spec {
+            report.errors.push(format!(
+                "format differs from the other samples {}",
+                format_spec(common_spec)
+            ));
+        }
+    }
+}
+fn format_spec(spec: &WavSpec) -> String {
+    format!(
+        "{}Hz {}ch {}{}",
+        spec.sample_rate,
+        spec.channels,
+        match spec.sample_format {
+            hound::SampleFormat::Float => "f",
+            hound::SampleFormat::Int => "i",
+        },
+        spec.bits_per_sample
+    )
+}
+
+/// Returns the sample without the leading and trailing silence, as a float wav file.
+pub(crate) fn trim_sample(report: &SampleReport, samples: &[f32]) -> Result<Vec<u8>, String> {
+    let spec = WavSpec {
+        sample_format: hound::SampleFormat::Float,
+        bits_per_sample: 32,
+        ..report.spec
+    };
+    let mut buffer = Cursor::new(Vec::new());
+    let mut writer = WavWriter::new(&mut buffer, spec).map_err(|err| err.to_string())?;
+    for sample in &samples[report.voiced_range.clone()] {
+        writer
+            .write_sample(*sample)
+            .map_err(|err| err.to_string())?;
+    }
+    writer.finalize().map_err(|err| err.to_string())?;
+    Ok(buffer.into_inner())
+}
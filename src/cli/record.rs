@@ -1,19 +1,32 @@
+use std::collections::VecDeque;
 use std::fs::File;
 use std::io::BufWriter;
+use std::path::Path;
+use std::sync::mpsc;
 use std::sync::mpsc::Sender;
-use std::sync::{mpsc, Arc, Mutex};
 
 use clap::Args;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{FromSample, Sample, SampleRate, SizedSample};
 use gag::Gag;
+use hound::{WavSpec, WavWriter};
+
+use super::audio::get_rms;
+use super::converter::{AudioConverter, OutputFormat};
+use super::meter::LevelMeter;
+use super::provenance::{Provenance, ProvenanceArgs};
+use super::segments::{record_segments, SegmentSettings};
+
+/// Duration of the windows used to measure the speech level.
+const VAD_WINDOW_MS: usize = 30;
+
 #[derive(Args, Debug)]
 /// Record wav audio
 #[clap()]
 pub struct RecordCommand {
-    #[clap()]
-    /// Generated record path.
-    output_path: String,
+    #[clap(required_unless_present = "out_dir")]
+    /// Generated record path, used as base name for the takes and segments ("take.wav" generates "take-1.wav", "take-2.wav"...).
+    output_path: Option<String>,
     #[clap(short = 'i', long)]
     /// Input device index used for record.
     device_index: Option<usize>,
@@ -30,9 +43,44 @@ pub struct RecordCommand {
     /// Adjust the recording volume. value > 1.0 amplifies, value < 1.0 attenuates
     gain: f32,
     #[clap(long = "ms")]
-    /// Max record duration in milliseconds
+    /// Max record duration in milliseconds, applies to each take when vad is enabled.
     duration_ms: Option<u64>,
+    #[clap(flatten)]
+    vad: VadSettings,
+    #[clap(long)]
+    /// Wait for the next take after saving one, instead of exiting.
+    rearm: bool,
+    #[clap(flatten)]
+    output_format: OutputFormat,
+    #[clap(flatten)]
+    segments: SegmentSettings,
+    #[clap(flatten)]
+    provenance: ProvenanceArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+/// Options to record takes delimited by the speech level.
+pub(crate) struct VadSettings {
+    #[clap(long = "vad")]
+    /// Wait for speech to start each take and stop it after a silence, trimming the silence at both ends.
+    pub enabled: bool,
+    #[clap(long, default_value_t = -40., allow_hyphen_values = true)]
+    /// Level in dBFS over which the audio is considered speech.
+    pub vad_threshold_db: f32,
+    #[clap(long, default_value_t = 300)]
+    /// Milliseconds of audio kept before the speech starts and after it ends.
+    pub pre_roll_ms: usize,
+    #[clap(long, default_value_t = 1000)]
+    /// Milliseconds of silence that ends a take.
+    pub silence_ms: usize,
 }
+
+/// Audio captured by the input stream, or a request to stop the recording.
+pub(crate) enum RecordEvent {
+    Samples(Vec<f32>),
+    Stop,
+}
+
 pub fn record(command: RecordCommand) -> Result<(), String> {
     let mut stderr_gag = None;
     if !command.host_warnings {
@@ -40,6 +88,7 @@ pub fn record(command: RecordCommand) -> Result<(), String> {
     }
     //get the host
     let host = cpal::default_host();
+    let host_name = host.id().name();
 
     //get the default input device
     // Set up the input device and stream with the default input config.
@@ -62,82 +111,131 @@ pub fn record(command: RecordCommand) -> Result<(), String> {
         drop(stderr_gag);
     }
     // Create wav spec
-    let spec = wav_spec_from_config(&device_config);
-    let writer = hound::WavWriter::create(&command.output_path, spec).unwrap();
-    let writer = Arc::new(Mutex::new(Some(writer)));
-    println!("Begin recording...");
+    let device_spec = wav_spec_from_config(&device_config);
+    let spec = command.output_format.get_spec(&device_spec);
+    let provenance = Provenance::new(
+        host_name,
+        &device,
+        &device_config,
+        command.gain,
+        &command.provenance,
+    );
+    let mut converter = AudioConverter::new(&device_spec, &spec)?;
+    let max_samples = command.duration_ms.map(|ms| {
+        ((spec.sample_rate as f32 / 1000.) * (ms as f32) * spec.channels as f32) as usize
+    });
     // Run the input stream on a separate thread.
-    let writer_2 = writer.clone();
     let (tx, rx) = mpsc::channel();
-    let remaining_samples = command
-        .duration_ms
-        .map(|ms| ((spec.sample_rate as f32 / 1000.) * (ms as f32) * spec.channels as f32) as u64);
-    let stream = match device_config.sample_format() {
-        cpal::SampleFormat::I8 => new_record_stream::<i8, i8>(
-            &device,
-            device_config,
-            writer_2,
-            &tx,
-            command.gain,
-            remaining_samples,
-        )?,
-        cpal::SampleFormat::I16 => new_record_stream::<i16, i16>(
-            &device,
-            device_config,
-            writer_2,
-            &tx,
-            command.gain,
-            remaining_samples,
-        )?,
-        cpal::SampleFormat::I32 => new_record_stream::<i32, i32>(
-            &device,
-            device_config,
-            writer_2,
-            &tx,
-            command.gain,
-            remaining_samples,
-        )?,
-        cpal::SampleFormat::F32 => new_record_stream::<f32, f32>(
-            &device,
-            device_config,
-            writer_2,
-            &tx,
-            command.gain,
-            remaining_samples,
-        )?,
-        _ => return Err("Only support sample formats: i16, i32, f32".to_string())?,
-    };
+    let stream = new_input_stream(&device, device_config, &tx, command.gain)?;
+    let mut meter = LevelMeter::new(device_spec.sample_rate, device_spec.channels, true);
     stream.play().expect("Unable to record");
-    if let Some(duration_ms) = command.duration_ms {
-        println!("Stopping in {}ms.", duration_ms);
+    ctrlc::set_handler(move || {
+        tx.send(RecordEvent::Stop)
+            .expect("Could not send signal on channel.")
+    })
+    .expect("Unable to listen keyboard");
+    let output_path = command.output_path.unwrap_or_default();
+    if command.segments.segment_secs.is_some() {
+        record_segments(
+            &rx,
+            &mut converter,
+            &mut meter,
+            &command.segments,
+            &output_path,
+            command.vad.vad_threshold_db,
+            &provenance,
+        )?;
+        drop(stream);
+    } else if command.vad.enabled {
+        println!("Press 'Ctrl + c' to stop.");
+        let mut take_recorder = TakeRecorder::new(&command.vad, &spec, max_samples);
+        println!("Waiting for speech...");
+        for event in rx.iter() {
+            let samples = match event {
+                RecordEvent::Samples(samples) => {
+                    meter.add_samples(&samples);
+                    converter.process(&samples)?
+                }
+                RecordEvent::Stop => break,
+            };
+            let was_recording = take_recorder.is_recording();
+            let takes = take_recorder.add_samples(&samples);
+            if !was_recording && take_recorder.is_recording() {
+                meter.clear();
+                println!("Recording take...");
+            }
+            for take in takes {
+                meter.clear();
+                save_take(&output_path, &spec, &take, &provenance)?;
+                if !command.rearm {
+                    return Ok(());
+                }
+                println!("Waiting for speech...");
+            }
+        }
+        drop(stream);
+        take_recorder.add_samples(&converter.finish()?);
+        if let Some(take) = take_recorder.finish() {
+            meter.clear();
+            save_take(&output_path, &spec, &take, &provenance)?;
+        }
+    } else {
+        let mut writer = WavWriter::create(&output_path, spec).map_err(|err| err.to_string())?;
+        println!("Begin recording...");
+        if let Some(duration_ms) = command.duration_ms {
+            println!("Stopping in {}ms.", duration_ms);
+        }
+        println!("Press 'Ctrl + c' to stop.");
+        let mut remaining_samples = max_samples;
+        for event in rx.iter() {
+            let samples = match event {
+                RecordEvent::Samples(samples) => {
+                    meter.add_samples(&samples);
+                    converter.process(&samples)?
+                }
+                RecordEvent::Stop => break,
+            };
+            write_limited_samples(&mut writer, &spec, samples, &mut remaining_samples)?;
+            if remaining_samples == Some(0) {
+                break;
+            }
+        }
+        drop(stream);
+        let samples = converter.finish()?;
+        write_limited_samples(&mut writer, &spec, samples, &mut remaining_samples)?;
+        writer.finalize().expect("Unable to save file");
+        provenance.write_sidecar(&output_path)?;
+        meter.print_summary();
+        println!("Recording {} complete!", &output_path);
     }
-    ctrlc::set_handler(move || tx.send(()).expect("Could not send signal on channel."))
-        .expect("Unable to listen keyboard");
-    println!("Press 'Ctrl + c' to stop.");
-    rx.recv().expect("Program failed");
-    drop(stream);
-    writer
-        .lock()
-        .unwrap()
-        .take()
-        .unwrap()
-        .finalize()
-        .expect("Unable to save file");
-    println!("Recording {} complete!", &command.output_path);
     Ok(())
 }
 
-fn new_record_stream<T, U>(
+/// Builds an input stream that sends the captured audio, as float samples with the gain applied.
+pub(crate) fn new_input_stream(
+    device: &cpal::Device,
+    device_config: cpal::SupportedStreamConfig,
+    tx: &Sender<RecordEvent>,
+    gain: f32,
+) -> Result<cpal::Stream, String> {
+    match device_config.sample_format() {
+        cpal::SampleFormat::I8 => new_record_stream::<i8>(device, device_config, tx, gain),
+        cpal::SampleFormat::I16 => new_record_stream::<i16>(device, device_config, tx, gain),
+        cpal::SampleFormat::I32 => new_record_stream::<i32>(device, device_config, tx, gain),
+        cpal::SampleFormat::F32 => new_record_stream::<f32>(device, device_config, tx, gain),
+        _ => Err("Only support sample formats: i16, i32, f32".to_string()),
+    }
+}
+
+fn new_record_stream<T>(
     device: &cpal::Device,
     device_config: cpal::SupportedStreamConfig,
-    writer_2: Arc<Mutex<Option<hound::WavWriter<BufWriter<File>>>>>,
-    tx: &Sender<()>,
+    tx: &Sender<RecordEvent>,
     gain: f32,
-    mut remaining_samples: Option<u64>,
 ) -> Result<cpal::Stream, String>
 where
     T: Sample + SizedSample,
-    U: Sample + hound::Sample + FromSample<T>,
+    f32: FromSample<T>,
 {
     let err_fn = move |err| {
         eprintln!("an error occurred on stream: {}", err);
@@ -147,8 +245,12 @@ where
     device
         .build_input_stream(
             &device_config.into(),
-            move |data, _: &_| {
-                write_input_data::<T, U>(data, &writer_2, gain, &tx_clone, &mut remaining_samples)
+            move |data: &[T], _: &_| {
+                let samples = data
+                    .iter()
+                    .map(|sample| f32::from_sample(*sample) * gain)
+                    .collect();
+                tx_clone.send(RecordEvent::Samples(samples)).ok();
             },
             err_fn,
             None,
@@ -156,38 +258,196 @@ where
         .map_err(err_cb)
 }
 
-fn write_input_data<T, U>(
-    data: &[T],
-    writer: &Arc<Mutex<Option<hound::WavWriter<BufWriter<File>>>>>,
-    gain: f32,
-    tx: &Sender<()>,
-    remaining_samples: &mut Option<u64>,
-) where
-    T: Sample,
-    U: Sample + hound::Sample + FromSample<T>,
-{
-    if remaining_samples.is_some() && remaining_samples.as_ref().unwrap().eq(&0) {
-        return;
+/// Writes the samples that fit in the remaining ones, if limited.
+fn write_limited_samples(
+    writer: &mut WavWriter<BufWriter<File>>,
+    spec: &WavSpec,
+    mut samples: Vec<f32>,
+    remaining_samples: &mut Option<usize>,
+) -> Result<(), String> {
+    if let Some(remaining_samples) = remaining_samples.as_mut() {
+        samples.truncate(*remaining_samples);
+        *remaining_samples -= samples.len();
     }
-    if let Ok(mut guard) = writer.try_lock() {
-        if let Some(writer) = guard.as_mut() {
-            let gain_sample = Sample::from_sample(gain);
-            for &sample in data.iter() {
-                let sample: U = U::from_sample(sample.mul_amp(gain_sample));
-                writer.write_sample(sample).ok();
-                if let Some(remaining_samples) = remaining_samples.as_mut() {
-                    *remaining_samples -= 1;
-                    if *remaining_samples == 0 {
-                        tx.send(()).ok();
-                        break;
-                    }
+    write_samples(writer, spec, &samples)
+}
+/// Writes float samples converting them to the wav sample format.
+pub(crate) fn write_samples(
+    writer: &mut WavWriter<BufWriter<File>>,
+    spec: &WavSpec,
+    samples: &[f32],
+) -> Result<(), String> {
+    match (spec.sample_format, spec.bits_per_sample) {
+        (hound::SampleFormat::Int, 8) => write_converted_samples::<i8>(writer, samples),
+        (hound::SampleFormat::Int, 16) => write_converted_samples::<i16>(writer, samples),
+        (hound::SampleFormat::Int, 32) => write_converted_samples::<i32>(writer, samples),
+        (hound::SampleFormat::Float, 32) => write_converted_samples::<f32>(writer, samples),
+        _ => Err("Unsupported wav format".to_string()),
+    }
+}
+fn write_converted_samples<U: hound::Sample + Sample + FromSample<f32>>(
+    writer: &mut WavWriter<BufWriter<File>>,
+    samples: &[f32],
+) -> Result<(), String> {
+    for sample in samples {
+        writer
+            .write_sample(U::from_sample(*sample))
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+/// Writes the take to the first available path derived from the output path.
+fn save_take(
+    output_path: &str,
+    spec: &WavSpec,
+    samples: &[f32],
+    provenance: &Provenance,
+) -> Result<(), String> {
+    let take_path = get_take_path(output_path);
+    write_wav(&take_path, spec, samples)?;
+    provenance.write_sidecar(&take_path)?;
+    let mut meter = LevelMeter::new(spec.sample_rate, spec.channels, false);
+    meter.add_samples(samples);
+    meter.print_summary();
+    println!("Take {} saved.", take_path);
+    Ok(())
+}
+pub(crate) fn write_wav(path: &str, spec: &WavSpec, samples: &[f32]) -> Result<(), String> {
+    let mut writer = WavWriter::create(path, *spec).map_err(|err| err.to_string())?;
+    write_samples(&mut writer, spec, samples)?;
+    writer.finalize().map_err(|err| err.to_string())
+}
+/// Appends the first take number without an existing file to the output path stem.
+pub(crate) fn get_take_path(output_path: &str) -> String {
+    get_numbered_path(output_path, &mut 0)
+}
+/// Appends the first number, after the provided one, without an existing file to the path stem.
+/// The number is updated to the one used.
+pub(crate) fn get_numbered_path(output_path: &str, number: &mut usize) -> String {
+    let path = Path::new(output_path);
+    let stem = path
+        .file_stem()
+        .map_or("take".into(), |stem| stem.to_string_lossy());
+    let extension = path
+        .extension()
+        .map_or("wav".into(), |extension| extension.to_string_lossy());
+    loop {
+        *number += 1;
+        let numbered_path = path
+            .with_file_name(format!("{}-{}.{}", stem, number, extension))
+            .to_string_lossy()
+            .to_string();
+        if !Path::new(&numbered_path).exists() {
+            return numbered_path;
+        }
+    }
+}
+
+/// Splits the captured audio into takes using the level of each window.
+pub(crate) struct TakeRecorder {
+    threshold: f32,
+    window_size: usize,
+    pre_roll_size: usize,
+    silence_size: usize,
+    max_samples: Option<usize>,
+    window: Vec<f32>,
+    pre_roll: VecDeque<f32>,
+    take: Option<Take>,
+}
+struct Take {
+    samples: Vec<f32>,
+    /// Take length at the end of the last window with speech.
+    speech_end: usize,
+}
+impl TakeRecorder {
+    pub(crate) fn new(
+        settings: &VadSettings,
+        spec: &WavSpec,
+        max_samples: Option<usize>,
+    ) -> TakeRecorder {
+        // from the duration, as the samples per millisecond are fractional on rates like 22050
+        let get_size = |ms: usize| ms * spec.sample_rate as usize / 1000 * spec.channels as usize;
+        let window_size = get_size(VAD_WINDOW_MS);
+        TakeRecorder {
+            threshold: 10_f32.powf(settings.vad_threshold_db / 20.),
+            window_size,
+            pre_roll_size: get_size(settings.pre_roll_ms),
+            silence_size: get_size(settings.silence_ms),
+            max_samples,
+            window: Vec::with_capacity(window_size),
+            pre_roll: VecDeque::new(),
+            take: None,
+        }
+    }
+    /// Returns the takes completed by the samples.
+    pub(crate) fn add_samples(&mut self, samples: &[f32]) -> Vec<Vec<f32>> {
+        let mut takes = Vec::new();
+        for sample in samples {
+            self.window.push(*sample);
+            if self.window.len() == self.window_size {
+                if let Some(take) = self.add_window() {
+                    takes.push(take);
                 }
+                self.window.clear();
             }
         }
+        takes
+    }
+    pub(crate) fn is_recording(&self) -> bool {
+        self.take.is_some()
+    }
+    /// Returns the take in progress, if any.
+    pub(crate) fn finish(&mut self) -> Option<Vec<f32>> {
+        self.take.take().map(|take| self.trim(take))
+    }
+    fn add_window(&mut self) -> Option<Vec<f32>> {
+        let is_speech = get_rms(&self.window) >= self.threshold;
+        match self.take.as_mut() {
+            None => {
+                if is_speech {
+                    // the take starts with the pre-roll followed by the window with speech
+                    let mut samples = self.pre_roll.drain(..).collect::<Vec<_>>();
+                    samples.extend_from_slice(&self.window);
+                    let speech_end = samples.len();
+                    samples.reserve(self.silence_size);
+                    self.take = Some(Take {
+                        samples,
+                        speech_end,
+                    });
+                } else {
+                    self.pre_roll.extend(self.window.iter());
+                    let excess = self.pre_roll.len().saturating_sub(self.pre_roll_size);
+                    self.pre_roll.drain(..excess);
+                }
+                None
+            }
+            Some(take) => {
+                take.samples.extend_from_slice(&self.window);
+                if is_speech {
+                    take.speech_end = take.samples.len();
+                }
+                let silence_ended = take.samples.len() - take.speech_end >= self.silence_size;
+                let max_reached = self
+                    .max_samples
+                    .is_some_and(|max_samples| take.samples.len() >= max_samples);
+                if silence_ended || max_reached {
+                    self.finish()
+                } else {
+                    None
+                }
+            }
+        }
+    }
+    fn trim(&self, mut take: Take) -> Vec<f32> {
+        let mut len = (take.speech_end + self.pre_roll_size).min(take.samples.len());
+        if let Some(max_samples) = self.max_samples {
+            len = len.min(max_samples);
+        }
+        take.samples.truncate(len);
+        take.samples
     }
 }
-
-fn wav_spec_from_config(config: &cpal::SupportedStreamConfig) -> hound::WavSpec {
+pub(crate) fn wav_spec_from_config(config: &cpal::SupportedStreamConfig) -> hound::WavSpec {
     hound::WavSpec {
         channels: config.channels() as _,
         sample_rate: config.sample_rate().0 as _,
@@ -284,3 +544,67 @@ pub(crate) fn get_device(device_index: Option<usize>, host: cpal::Host) -> cpal:
         )
         .expect("Failed to find input device")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_take_recorder(pre_roll_ms: usize) -> TakeRecorder {
+        let settings = VadSettings {
+            enabled: true,
+            vad_threshold_db: -40.,
+            pre_roll_ms,
+            silence_ms: 90,
+        };
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 1000,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        TakeRecorder::new(&settings, &spec, None)
+    }
+
+    #[test]
+    fn keeps_the_first_speech_window_without_pre_roll() {
+        let mut take_recorder = get_take_recorder(0);
+        let mut samples = vec![0.; 60];
+        samples.extend(vec![0.5; 60]);
+        samples.extend(vec![0.; 120]);
+        let takes = take_recorder.add_samples(&samples);
+        assert_eq!(takes, vec![vec![0.5; 60]]);
+    }
+
+    #[test]
+    fn keeps_the_pre_roll_before_the_speech() {
+        let mut take_recorder = get_take_recorder(30);
+        let mut samples = vec![0.001; 60];
+        samples.extend(vec![0.5; 60]);
+        samples.extend(vec![0.; 120]);
+        let takes = take_recorder.add_samples(&samples);
+        assert_eq!(takes.len(), 1);
+        assert_eq!(takes[0].len(), 30 + 60 + 30);
+        assert!(takes[0][..30].iter().all(|sample| *sample == 0.001));
+        assert!(takes[0][30..90].iter().all(|sample| *sample == 0.5));
+    }
+
+    #[test]
+    fn computes_the_sizes_from_the_duration() {
+        let settings = VadSettings {
+            enabled: true,
+            vad_threshold_db: -40.,
+            pre_roll_ms: 300,
+            silence_ms: 1000,
+        };
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 22050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let take_recorder = TakeRecorder::new(&settings, &spec, None);
+        assert_eq!(take_recorder.window_size, 661 * 2);
+        assert_eq!(take_recorder.pre_roll_size, 6615 * 2);
+        assert_eq!(take_recorder.silence_size, 22050 * 2);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    io::{BufRead, Write},
+    path::Path,
+    sync::mpsc,
+    time::Duration,
+};
+
+use clap::Args;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use gag::Gag;
+use hound::WavSpec;
+
+use super::converter::{AudioConverter, OutputFormat};
+use super::meter::LevelMeter;
+use super::provenance::{Provenance, ProvenanceArgs};
+use super::record::{
+    get_config, get_device, get_take_path, new_input_stream, wav_spec_from_config, write_wav,
+    RecordEvent, TakeRecorder, VadSettings,
+};
+
+#[derive(Args, Debug)]
+/// Record a set of takes for a training dataset
+#[clap()]
+pub struct RecordSessionCommand {
+    #[clap(long)]
+    /// Label of the takes, used as "[label]" on the file names. Unlabeled takes are "none" samples.
+    label: Option<String>,
+    #[clap(long, default_value_t = 50)]
+    /// Number of takes to keep.
+    count: usize,
+    #[clap(long)]
+    /// Dataset directory, takes are written to its "train" and "test" subdirectories.
+    out: String,
+    #[clap(long, default_value_t = 0.2)]
+    /// Fraction of the takes written to the test directory.
+    test_ratio: f32,
+    #[clap(long, default_value_t = 3)]
+    /// Seconds of countdown before each take.
+    countdown: u64,
+    #[clap(long = "ms", default_value_t = 2000)]
+    /// Take duration in milliseconds, max take duration when vad is enabled.
+    duration_ms: u64,
+    #[clap(flatten)]
+    vad: VadSettings,
+    #[clap(short = 'i', long)]
+    /// Input device index used for record.
+    device_index: Option<usize>,
+    #[clap(short, long)]
+    /// Input device configuration index used for record.
+    config_index: Option<usize>,
+    #[clap(short = 'w', long)]
+    /// Display host warnings
+    host_warnings: bool,
+    #[clap(long, default_value_t = 16000)]
+    /// Preferred sample rate, if not available for the selected config min sample rate is used.
+    sample_rate: u32,
+    #[clap(short, long, default_value_t = 1.)]
+    /// Adjust the recording volume. value > 1.0 amplifies, value < 1.0 attenuates
+    gain: f32,
+    #[clap(flatten)]
+    output_format: OutputFormat,
+    #[clap(flatten)]
+    provenance: ProvenanceArgs,
+}
+
+pub fn record_session(command: RecordSessionCommand) -> Result<(), String> {
+    if !(0. ..=1.).contains(&command.test_ratio) {
+        return Err("The test ratio should be in range 0 - 1".to_string());
+    }
+    let train_dir = Path::new(&command.out).join("train");
+    let test_dir = Path::new(&command.out).join("test");
+    for dir in [&train_dir, &test_dir] {
+        std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    let file_name = match command.label.as_ref() {
+        Some(label) => format!("[{}]take.wav", label),
+        None => "take.wav".to_string(),
+    };
+    let mut stderr_gag = None;
+    if !command.host_warnings {
+        stderr_gag = Some(Gag::stderr().unwrap());
+    }
+    let host = cpal::default_host();
+    let host_name = host.id().name();
+    let device = get_device(command.device_index, host);
+    println!(
+        "Input device: {}",
+        device.name().map_err(|err| err.to_string())?
+    );
+    let device_config = get_config(command.config_index, &device, command.sample_rate);
+    println!(
+        "Input device config: Sample Rate: {}, Channels: {}, Format: {}",
+        device_config.sample_rate().0,
+        device_config.channels(),
+        device_config.sample_format()
+    );
+    // disable gag after device config
+    if let Some(stderr_gag) = stderr_gag {
+        drop(stderr_gag);
+    }
+    let device_spec = wav_spec_from_config(&device_config);
+    let spec = command.output_format.get_spec(&device_spec);
+    let provenance = Provenance::new(
+        host_name,
+        &device,
+        &device_config,
+        command.gain,
+        &command.provenance,
+    );
+    let max_samples =
+        (spec.sample_rate as u64 * spec.channels as u64 * command.duration_ms / 1000) as usize;
+    let (tx, rx) = mpsc::channel();
+    let stream = new_input_stream(&device, device_config, &tx, command.gain)?;
+    stream.play().expect("Unable to record");
+    println!(
+        "Recording {} takes of {} into {}!",
+        command.count,
+        command.label.as_deref().unwrap_or("none"),
+        command.out
+    );
+    let mut stdin = std::io::stdin().lock();
+    let mut test_takes = 0;
+    let mut kept_takes = 0;
+    while kept_takes < command.count {
+        for remaining in (1..=command.countdown).rev() {
+            print!(
+                "Take {} of {} in {}...\r",
+                kept_takes + 1,
+                command.count,
+                remaining
+            );
+            std::io::stdout().flush().ok();
+            std::thread::sleep(Duration::from_secs(1));
+        }
+        // discard the audio captured while waiting
+        rx.try_iter().for_each(drop);
+        println!(
+            "Take {} of {}: speak now!        ",
+            kept_takes + 1,
+            command.count
+        );
+        let mut converter = AudioConverter::new(&device_spec, &spec)?;
+        let mut meter = LevelMeter::new(device_spec.sample_rate, device_spec.channels, true);
+        let take = if command.vad.enabled {
+            record_vad_take(
+                &rx,
+                &mut converter,
+                &mut meter,
+                &command.vad,
+                &spec,
+                max_samples,
+            )?
+        } else {
+            record_fixed_take(&rx, &mut converter, &mut meter, max_samples)?
+        };
+        meter.clear();
+        let mut take_meter = LevelMeter::new(spec.sample_rate, spec.channels, false);
+        take_meter.add_samples(&take);
+        take_meter.print_summary();
+        print!("Keep take? [Enter] keep, [r] redo, [q] quit: ");
+        std::io::stdout().flush().ok();
+        let mut answer = String::new();
+        stdin
+            .read_line(&mut answer)
+            .map_err(|err| err.to_string())?;
+        match answer.trim() {
+            "q" => break,
+            "r" => continue,
+            _ => {}
+        }
+        // keep the test fraction as close to the ratio as possible
+        let is_test = (test_takes + 1) as f32 <= command.test_ratio * (kept_takes + 1) as f32;
+        let dir = if is_test { &test_dir } else { &train_dir };
+        let take_path = get_take_path(&dir.join(&file_name).to_string_lossy());
+        write_wav(&take_path, &spec, &take)?;
+        provenance.write_sidecar(&take_path)?;
+        println!("Saved {}", take_path);
+        kept_takes += 1;
+        if is_test {
+            test_takes += 1;
+        }
+    }
+    drop(stream);
+    println!(
+        "Session finished, {} takes kept ({} train, {} test).",
+        kept_takes,
+        kept_takes - test_takes,
+        test_takes
+    );
+    Ok(())
+}
+
+fn record_fixed_take(
+    rx: &mpsc::Receiver<RecordEvent>,
+    converter: &mut AudioConverter,
+    meter: &mut LevelMeter,
+    max_samples: usize,
+) -> Result<Vec<f32>, String> {
+    let mut take = Vec::with_capacity(max_samples);
+    for event in rx.iter() {
+        if let RecordEvent::Samples(samples) = event {
+            meter.add_samples(&samples);
+            let samples = converter.process(&samples)?;
+            take.extend_from_slice(&samples[..samples.len().min(max_samples - take.len())]);
+        }
+        if take.len() == max_samples {
+            break;
+        }
+    }
+    Ok(take)
+}
+fn record_vad_take(
+    rx: &mpsc::Receiver<RecordEvent>,
+    converter: &mut AudioConverter,
+    meter: &mut LevelMeter,
+    settings: &VadSettings,
+    spec: &WavSpec,
+    max_samples: usize,
+) -> Result<Vec<f32>, String> {
+    let mut take_recorder = TakeRecorder::new(settings, spec, Some(max_samples));
+    for event in rx.iter() {
+        if let RecordEvent::Samples(samples) = event {
+            meter.add_samples(&samples);
+            let samples = converter.process(&samples)?;
+            if let Some(take) = take_recorder.add_samples(&samples).into_iter().next() {
+                return Ok(take);
+            }
+        }
+    }
+    Ok(take_recorder.finish().unwrap_or_default())
+}
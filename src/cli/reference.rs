@@ -0,0 +1,331 @@
+use std::{collections::HashMap, path::Path};
+
+use clap::{Args, Subcommand};
+use rustpotter::{WakewordLoad, WakewordRef, WakewordRefBuildFromBuffers, WakewordSave};
+
+use super::{
+    filter::{filter_sample, get_median_rms_level, get_sample_rms_level},
+    metadata::{
+        get_samples_dir, read_reference_samples, write_reference_samples, WakewordMetadata,
+    },
+    spot::MFCC_FRAME_SHIFT_MS,
+};
+
+#[derive(Args, Debug)]
+/// Update the samples of a wakeword reference.
+#[clap()]
+pub struct RefCommand {
+    #[clap(subcommand)]
+    action: RefAction,
+}
+#[derive(Subcommand, Debug)]
+enum RefAction {
+    /// Add wav samples to the wakeword reference
+    Add(RefAddCommand),
+    /// Remove samples from the wakeword reference by name
+    Remove(RefRemoveCommand),
+    /// List the samples of the wakeword reference
+    List(RefListCommand),
+}
+#[derive(Args, Debug)]
+struct RefAddCommand {
+    #[clap()]
+    /// Wakeword reference path.
+    model_path: String,
+    #[clap(num_args = 1.., required = true)]
+    /// List of sample record paths, the file names are used as sample names.
+    /// The audio filters used to build the reference are applied to them.
+    sample_path: Vec<String>,
+}
+#[derive(Args, Debug)]
+struct RefRemoveCommand {
+    #[clap()]
+    /// Wakeword reference path.
+    model_path: String,
+    #[clap(num_args = 1.., required = true)]
+    /// List of sample names, as displayed by the list command.
+    sample_name: Vec<String>,
+}
+#[derive(Args, Debug)]
+struct RefListCommand {
+    #[clap()]
+    /// Wakeword reference path.
+    model_path: String,
+}
+
+pub fn reference(command: RefCommand) -> Result<(), String> {
+    match command.action {
+        RefAction::Add(command) => add_samples(command),
+        RefAction::Remove(command) => remove_samples(command),
+        RefAction::List(command) => list_samples(command),
+    }
+}
+fn add_samples(command: RefAddCommand) -> Result<(), String> {
+    let mut wakeword = WakewordRef::load_from_file(&command.model_path)?;
+    let mut metadata =
+        WakewordMetadata::load(&command.model_path)?.unwrap_or_else(WakewordMetadata::new);
+    for path in &command.sample_path {
+        let sample_name = Path::new(path)
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if wakeword.samples_features.contains_key(&sample_name) {
+            return Err(format!("Sample {} already exists", sample_name));
+        }
+        let mut buffer = std::fs::read(path).map_err(|err| err.to_string())?;
+        // the detector measures the rms level before filtering
+        let rms_level = get_sample_rms_level(&buffer)?;
+        if let Some(filters) = metadata.filters.as_ref() {
+            buffer = filter_sample(&buffer, filters, wakeword.rms_level)?;
+        }
+        let sample_wakeword = WakewordRef::new_from_sample_buffers(
+            wakeword.name.clone(),
+            None,
+            None,
+            HashMap::from([(sample_name.clone(), buffer.clone())]),
+            wakeword.mfcc_size,
+        )?;
+        write_reference_samples(&command.model_path, [(&sample_name, &buffer)].into_iter())?;
+        wakeword
+            .samples_features
+            .extend(sample_wakeword.samples_features);
+        metadata
+            .sample_rms_levels
+            .insert(sample_name.clone(), rms_level);
+        println!("Sample {} added.", sample_name);
+    }
+    save_reference(wakeword, metadata, &command.model_path)
+}
+fn remove_samples(command: RefRemoveCommand) -> Result<(), String> {
+    let mut wakeword = WakewordRef::load_from_file(&command.model_path)?;
+    let mut metadata =
+        WakewordMetadata::load(&command.model_path)?.unwrap_or_else(WakewordMetadata::new);
+    for sample_name in &command.sample_name {
+        if wakeword.samples_features.remove(sample_name).is_none() {
+            return Err(format!("Sample {} not found", sample_name));
+        }
+        metadata.sample_rms_levels.remove(sample_name);
+        println!("Sample {} removed.", sample_name);
+    }
+    if wakeword.samples_features.is_empty() {
+        return Err("Can not remove all the samples".to_string());
+    }
+    save_reference(wakeword, metadata, &command.model_path)?;
+    let samples_dir = get_samples_dir(&command.model_path);
+    for sample_name in &command.sample_name {
+        let sample_path = samples_dir.join(sample_name);
+        if sample_path.exists() {
+            std::fs::remove_file(sample_path).map_err(|err| err.to_string())?;
+        }
+    }
+    Ok(())
+}
+fn list_samples(command: RefListCommand) -> Result<(), String> {
+    let wakeword = WakewordRef::load_from_file(&command.model_path)?;
+    let sample_rms_levels = WakewordMetadata::load(&command.model_path)?
+        .map(|metadata| metadata.sample_rms_levels)
+        .unwrap_or_default();
+    println!(
+        "{}: {} samples, mfcc size {}, rms level {:.4}, threshold {}, averaged threshold {}",
+        wakeword.name,
+        wakeword.samples_features.len(),
+        wakeword.mfcc_size,
+        wakeword.rms_level,
+        wakeword
+            .threshold
+            .map_or("unset".to_string(), |threshold| threshold.to_string()),
+        wakeword
+            .avg_threshold
+            .map_or("unset".to_string(), |threshold| threshold.to_string()),
+    );
+    let mut sample_names = wakeword.samples_features.keys().collect::<Vec<_>>();
+    sample_names.sort();
+    for sample_name in sample_names {
+        println!(
+            "{}: rms level {}, {}ms",
+            sample_name,
+            sample_rms_levels
+                .get(sample_name)
+                .map_or("unknown".to_string(), |rms_level| format!(
+                    "{:.4}",
+                    rms_level
+                )),
+            wakeword.samples_features[sample_name].len() * MFCC_FRAME_SHIFT_MS
+        );
+    }
+    Ok(())
+}
+/// Recomputes the averaged features and the rms level, and saves the reference and its metadata.
+fn save_reference(
+    mut wakeword: WakewordRef,
+    metadata: WakewordMetadata,
+    path: &str,
+) -> Result<(), String> {
+    // the library only averages the features of wav samples, so the reference is rebuilt from the stored ones
+    let samples = read_reference_samples(path, wakeword.samples_features.keys())?;
+    wakeword.avg_features = match samples {
+        Some(samples) => {
+            WakewordRef::new_from_sample_buffers(
+                wakeword.name.clone(),
+                None,
+                None,
+                samples,
+                wakeword.mfcc_size,
+            )?
+            .avg_features
+        }
+        None => {
+            println!(
+                "Warning: some samples are missing from {}, the averaged features are removed.",
+                get_samples_dir(path).display()
+            );
+            None
+        }
+    };
+    let rms_levels = wakeword
+        .samples_features
+        .keys()
+        .map(|sample_name| metadata.sample_rms_levels.get(sample_name).copied())
+        .collect::<Option<Vec<_>>>();
+    match rms_levels {
+        Some(rms_levels) => wakeword.rms_level = get_median_rms_level(rms_levels.into_iter()),
+        None => println!(
+            "Warning: the rms level of some samples is unknown, the reference rms level {:.4} is kept.",
+            wakeword.rms_level
+        ),
+    }
+    wakeword.save_to_file(path)?;
+    metadata.write(path)?;
+    println!(
+        "{} saved with {} samples!",
+        path,
+        wakeword.samples_features.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, path::PathBuf};
+
+    use hound::{SampleFormat, WavSpec, WavWriter};
+
+    use super::*;
+
+    /// Returns a wav file with a tone whose frequency rises along its duration.
+    fn get_sample_buffer(duration_ms: usize, start_frequency: f32, volume: f32) -> Vec<u8> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut buffer = Vec::new();
+        let mut writer = WavWriter::new(Cursor::new(&mut buffer), spec).unwrap();
+        let samples = duration_ms * 16;
+        let mut phase = 0f32;
+        for index in 0..samples {
+            let frequency = start_frequency + 600. * index as f32 / samples as f32;
+            phase += std::f32::consts::TAU * frequency / 16000.;
+            writer
+                .write_sample((phase.sin() * volume * i16::MAX as f32) as i16)
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+        buffer
+    }
+
+    /// Creates an empty directory for the reference files.
+    fn create_model_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "rustpotter-cli-reference-{}-{}",
+            name,
+            std::process::id()
+        ));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+    /// Saves a reference built from the samples along with its stored samples.
+    fn save_model(model_path: &str, samples: &HashMap<String, Vec<u8>>) {
+        WakewordRef::new_from_sample_buffers("test".to_string(), None, None, samples.clone(), 16)
+            .unwrap()
+            .save_to_file(model_path)
+            .unwrap();
+        write_reference_samples(model_path, samples.iter()).unwrap();
+    }
+    fn get_avg_features(samples: HashMap<String, Vec<u8>>) -> Option<Vec<Vec<f32>>> {
+        WakewordRef::new_from_sample_buffers("test".to_string(), None, None, samples, 16)
+            .unwrap()
+            .avg_features
+    }
+
+    #[test]
+    fn avg_features_are_rebuilt_from_the_stored_samples() {
+        let dir = create_model_dir("rebuild");
+        let model_path = dir.join("test.rpw").to_string_lossy().to_string();
+        let mut samples = HashMap::from([
+            ("a.wav".to_string(), get_sample_buffer(900, 300., 0.3)),
+            ("b.wav".to_string(), get_sample_buffer(1100, 350., 0.5)),
+            ("c.wav".to_string(), get_sample_buffer(1000, 250., 0.2)),
+        ]);
+        save_model(&model_path, &samples);
+        let new_sample = get_sample_buffer(1100, 400., 0.4);
+        let new_sample_path = dir.join("d.wav");
+        std::fs::write(&new_sample_path, &new_sample).unwrap();
+        add_samples(RefAddCommand {
+            model_path: model_path.clone(),
+            sample_path: vec![new_sample_path.to_string_lossy().to_string()],
+        })
+        .unwrap();
+        samples.insert("d.wav".to_string(), new_sample);
+        let wakeword = WakewordRef::load_from_file(&model_path).unwrap();
+        assert_eq!(wakeword.avg_features, get_avg_features(samples.clone()));
+        remove_samples(RefRemoveCommand {
+            model_path: model_path.clone(),
+            sample_name: vec!["b.wav".to_string()],
+        })
+        .unwrap();
+        samples.remove("b.wav");
+        let wakeword = WakewordRef::load_from_file(&model_path).unwrap();
+        assert_eq!(wakeword.avg_features, get_avg_features(samples));
+        assert!(!get_samples_dir(&model_path).join("b.wav").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn avg_features_are_removed_without_the_stored_samples() {
+        let dir = create_model_dir("missing");
+        let model_path = dir.join("test.rpw").to_string_lossy().to_string();
+        let samples = HashMap::from([
+            ("a.wav".to_string(), get_sample_buffer(900, 300., 0.3)),
+            ("b.wav".to_string(), get_sample_buffer(1100, 350., 0.5)),
+            ("c.wav".to_string(), get_sample_buffer(1000, 250., 0.2)),
+        ]);
+        save_model(&model_path, &samples);
+        std::fs::remove_file(get_samples_dir(&model_path).join("a.wav")).unwrap();
+        remove_samples(RefRemoveCommand {
+            model_path: model_path.clone(),
+            sample_name: vec!["b.wav".to_string()],
+        })
+        .unwrap();
+        let wakeword = WakewordRef::load_from_file(&model_path).unwrap();
+        assert_eq!(wakeword.samples_features.len(), 2);
+        assert!(wakeword.avg_features.is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sample_rms_level_matches_the_library() {
+        let buffer = get_sample_buffer(1000, 300., 0.3);
+        let wakeword = WakewordRef::new_from_sample_buffers(
+            "test".to_string(),
+            None,
+            None,
+            HashMap::from([("a.wav".to_string(), buffer.clone())]),
+            16,
+        )
+        .unwrap();
+        assert_eq!(get_sample_rms_level(&buffer).unwrap(), wakeword.rms_level);
+    }
+}
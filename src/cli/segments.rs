@@ -0,0 +1,277 @@
+use std::{path::Path, sync::mpsc::Receiver};
+
+use clap::Args;
+use hound::WavSpec;
+use rustpotter::BandPassFilter;
+
+use super::{
+    converter::AudioConverter,
+    meter::LevelMeter,
+    provenance::Provenance,
+    record::{get_numbered_path, write_wav, RecordEvent},
+};
+
+/// Duration of the windows used to measure the speech level.
+const SPEECH_WINDOW_MS: usize = 30;
+/// Consecutive milliseconds over the threshold considered speech.
+const SPEECH_MIN_MS: usize = 300;
+/// Band of frequencies used to measure the speech level.
+const SPEECH_LOW_CUTOFF: f32 = 300.;
+const SPEECH_HIGH_CUTOFF: f32 = 3400.;
+/// Size of the wav header written by hound.
+const WAV_HEADER_SIZE: u64 = 44;
+
+#[derive(Args, Debug)]
+/// Options to record continuously into fixed length files.
+pub(crate) struct SegmentSettings {
+    #[clap(long, conflicts_with = "enabled")]
+    /// Record continuously, splitting the audio into files of this number of seconds named by appending a number to the output path.
+    pub segment_secs: Option<usize>,
+    #[clap(long, requires = "segment_secs")]
+    /// Directory where the segments are written.
+    pub out_dir: Option<String>,
+    #[clap(long, requires = "segment_secs", value_parser = parse_duration)]
+    /// Stop after recording this duration ("3h", "1h30m", "90s").
+    pub max_total: Option<usize>,
+    #[clap(long, requires = "segment_secs")]
+    /// Discard the segments containing speech, detected as 300ms over the vad threshold in the speech frequencies.
+    pub skip_speech: bool,
+    #[clap(long, requires = "segment_secs", value_parser = parse_size)]
+    /// Stop before the written segments exceed this size ("500M", "2G").
+    pub max_disk: Option<u64>,
+}
+
+/// Writes the captured audio into fixed length files until stopped or a limit is reached.
+pub(crate) fn record_segments(
+    rx: &Receiver<RecordEvent>,
+    converter: &mut AudioConverter,
+    meter: &mut LevelMeter,
+    settings: &SegmentSettings,
+    base_path: &str,
+    speech_threshold_db: f32,
+    provenance: &Provenance,
+) -> Result<(), String> {
+    let spec = *converter.get_output_spec();
+    let segment_secs = settings.segment_secs.unwrap_or(1).max(1);
+    let samples_per_sec = spec.sample_rate as usize * spec.channels as usize;
+    let segment_size = segment_secs * samples_per_sec;
+    let segment_bytes = segment_size as u64 * spec.bits_per_sample as u64 / 8 + WAV_HEADER_SIZE;
+    let mut remaining_samples = settings
+        .max_total
+        .map(|max_total_ms| max_total_ms * samples_per_sec / 1000);
+    let mut speech_detector = if settings.skip_speech {
+        Some(SpeechDetector::new(&spec, speech_threshold_db))
+    } else {
+        None
+    };
+    let path = match settings.out_dir.as_ref() {
+        Some(out_dir) => {
+            std::fs::create_dir_all(out_dir).map_err(|err| err.to_string())?;
+            let file_name = Path::new(base_path)
+                .file_name()
+                .map_or("segment.wav".into(), |file_name| {
+                    file_name.to_string_lossy()
+                });
+            Path::new(out_dir)
+                .join(file_name.as_ref())
+                .to_string_lossy()
+                .to_string()
+        }
+        None => base_path.to_string(),
+    };
+    println!("Recording segments of {}s...", segment_secs);
+    println!("Press 'Ctrl + c' to stop.");
+    let mut segment = Vec::with_capacity(segment_size);
+    let mut segment_number = 0;
+    let mut written_bytes = 0;
+    let mut saved_segments = 0;
+    let mut skipped_segments = 0;
+    'record: for event in rx.iter() {
+        let mut samples = match event {
+            RecordEvent::Samples(samples) => {
+                meter.add_samples(&samples);
+                converter.process(&samples)?
+            }
+            RecordEvent::Stop => break,
+        };
+        if let Some(remaining_samples) = remaining_samples.as_mut() {
+            samples.truncate(*remaining_samples);
+            *remaining_samples -= samples.len();
+        }
+        for sample in samples {
+            segment.push(sample);
+            if segment.len() < segment_size {
+                continue;
+            }
+            if speech_detector
+                .as_mut()
+                .is_some_and(|speech_detector| speech_detector.contains_speech(&segment))
+            {
+                skipped_segments += 1;
+                meter.clear();
+                println!("Segment skipped, speech detected.");
+            } else {
+                if settings
+                    .max_disk
+                    .is_some_and(|max_disk| written_bytes + segment_bytes > max_disk)
+                {
+                    meter.clear();
+                    println!("Disk usage limit reached.");
+                    break 'record;
+                }
+                let segment_path = get_numbered_path(&path, &mut segment_number);
+                write_wav(&segment_path, &spec, &segment)?;
+                provenance.write_sidecar(&segment_path)?;
+                written_bytes += segment_bytes;
+                saved_segments += 1;
+                meter.clear();
+                println!("Segment {} saved.", segment_path);
+            }
+            segment.clear();
+        }
+        if remaining_samples == Some(0) {
+            break;
+        }
+    }
+    meter.clear();
+    println!(
+        "Recording complete, {} segments saved ({}s), {} skipped.",
+        saved_segments,
+        saved_segments * segment_secs,
+        skipped_segments
+    );
+    Ok(())
+}
+
+/// Detects speech as sustained level over a threshold on the speech frequencies.
+struct SpeechDetector {
+    filter: BandPassFilter,
+    channels: usize,
+    threshold: f32,
+    window_size: usize,
+    min_windows: usize,
+}
+impl SpeechDetector {
+    fn new(spec: &WavSpec, threshold_db: f32) -> SpeechDetector {
+        SpeechDetector {
+            filter: BandPassFilter::new(
+                spec.sample_rate as f32,
+                SPEECH_LOW_CUTOFF,
+                SPEECH_HIGH_CUTOFF,
+            ),
+            channels: spec.channels as usize,
+            threshold: 10_f32.powf(threshold_db / 20.),
+            window_size: spec.sample_rate as usize * SPEECH_WINDOW_MS / 1000,
+            min_windows: SPEECH_MIN_MS / SPEECH_WINDOW_MS,
+        }
+    }
+    fn contains_speech(&mut self, samples: &[f32]) -> bool {
+        let mut mono_samples = samples
+            .chunks_exact(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() / self.channels as f32)
+            .collect::<Vec<_>>();
+        self.filter.filter(&mut mono_samples);
+        let mut loud_windows = 0;
+        for window in mono_samples.chunks_exact(self.window_size) {
+            let rms = (window.iter().map(|sample| sample * sample).sum::<f32>()
+                / window.len() as f32)
+                .sqrt();
+            if rms >= self.threshold {
+                loud_windows += 1;
+                if loud_windows >= self.min_windows {
+                    return true;
+                }
+            } else {
+                loud_windows = 0;
+            }
+        }
+        false
+    }
+}
+
+/// Parses a duration like "3h", "1h30m" or "90s" into milliseconds, plain numbers are seconds.
+fn parse_duration(value: &str) -> Result<usize, String> {
+    let invalid_value = || format!("Invalid duration '{}'", value);
+    if let Ok(seconds) = value.parse::<f64>() {
+        return (seconds > 0.)
+            .then_some((seconds * 1000.) as usize)
+            .ok_or_else(invalid_value);
+    }
+    let mut total_ms = 0.;
+    let mut number = String::new();
+    for char in value.chars() {
+        if char.is_ascii_digit() || char == '.' {
+            number.push(char);
+            continue;
+        }
+        let unit_ms = match char {
+            'h' => 3_600_000.,
+            'm' => 60_000.,
+            's' => 1_000.,
+            _ => return Err(invalid_value()),
+        };
+        total_ms += number.parse::<f64>().map_err(|_| invalid_value())? * unit_ms;
+        number.clear();
+    }
+    if !number.is_empty() || total_ms <= 0. {
+        return Err(invalid_value());
+    }
+    Ok(total_ms as usize)
+}
+/// Parses a size like "500M" or "2G" into bytes, plain numbers are bytes.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let invalid_value = || format!("Invalid size '{}'", value);
+    let upper_value = value.to_uppercase();
+    let number = upper_value.trim_end_matches('B');
+    let (number, unit) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1_u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|number| *number > 0.)
+        .map(|number| (number * unit as f64) as u64)
+        .ok_or_else(invalid_value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_accepts_seconds_and_units() {
+        assert_eq!(parse_duration("90"), Ok(90_000));
+        assert_eq!(parse_duration("1.5"), Ok(1_500));
+        assert_eq!(parse_duration("45s"), Ok(45_000));
+        assert_eq!(parse_duration("2m"), Ok(120_000));
+        assert_eq!(parse_duration("1h30m"), Ok(5_400_000));
+        assert_eq!(parse_duration("1m0.5s"), Ok(60_500));
+    }
+
+    #[test]
+    fn parse_duration_rejects_malformed_values() {
+        for value in ["", "0", "-5", "0s", "10x", "5m3", "h", "1.2.3s", "1 m"] {
+            assert!(parse_duration(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn parse_size_accepts_units() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("1.5k"), Ok(1_536));
+        assert_eq!(parse_size("10MB"), Ok(10 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_size("1tb"), Ok(1 << 40));
+    }
+
+    #[test]
+    fn parse_size_rejects_malformed_values() {
+        for value in ["", "0", "-1", "MB", "10X", "1.5.2M"] {
+            assert!(parse_size(value).is_err(), "{} was accepted", value);
+        }
+    }
+}
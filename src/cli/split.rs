@@ -0,0 +1,311 @@
+use std::{collections::BTreeMap, fs::File, path::Path};
+
+use clap::Args;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use regex::Regex;
+use serde::Serialize;
+
+use super::{audio::get_wav_paths, provenance::ProvenanceFilter};
+
+/// Label of the records without one, as used by the training.
+pub(crate) const NONE_LABEL: &str = "none";
+
+#[derive(Args, Debug)]
+/// Options to split a single data directory into the train and test sets.
+pub(crate) struct SplitSettings {
+    #[clap(long, conflicts_with_all = ["train_dir", "test_dir"])]
+    /// Data directory path, its records are split into the train and test sets keeping the label proportions.
+    /// The split is written next to the model as a json manifest.
+    pub data_dir: Option<String>,
+    #[clap(long, default_value_t = 0.15, requires = "data_dir")]
+    /// Ratio of the records of each label used for testing.
+    test_ratio: f32,
+    #[clap(long, value_parser = parse_regex, requires = "data_dir")]
+    /// Regex matched against the file names to keep the records of each group on the same side,
+    /// its first capture group, or the whole match, is the group name ("^(speaker\d+)_").
+    /// Records that do not match are grouped alone.
+    group_by: Option<Regex>,
+}
+
+/// Record paths of the train and test sets.
+pub(crate) struct DataSplit {
+    pub train: Vec<String>,
+    pub test: Vec<String>,
+}
+
+#[derive(Serialize)]
+/// Split written next to the model, file names are relative to the data directory.
+struct SplitManifest<'a> {
+    data_dir: &'a str,
+    test_ratio: f32,
+    group_by: Option<&'a str>,
+    seed: u64,
+    train: Vec<String>,
+    test: Vec<String>,
+}
+
+/// Splits the data directory records, assigning whole groups to the side that keeps each label closer to the test ratio.
+pub(crate) fn split_data_dir(
+    data_dir: &str,
+    settings: &SplitSettings,
+    seed: u64,
+    provenance_filter: &ProvenanceFilter,
+) -> Result<DataSplit, String> {
+    if !(settings.test_ratio > 0. && settings.test_ratio < 1.) {
+        return Err("The test ratio should be between 0 and 1".to_string());
+    }
+    let mut groups: BTreeMap<(bool, String), Vec<(String, String)>> = BTreeMap::new();
+    let mut label_counts: BTreeMap<String, usize> = BTreeMap::new();
+    for path in provenance_filter.filter_paths(get_wav_paths(data_dir)?)? {
+        let file_name = get_file_name(&path);
+        let label = get_label(&file_name);
+        *label_counts.entry(label.clone()).or_default() += 1;
+        let group = match settings
+            .group_by
+            .as_ref()
+            .and_then(|group_by| group_by.captures(&file_name))
+        {
+            Some(captures) => (
+                true,
+                captures
+                    .get(1)
+                    .or(captures.get(0))
+                    .map(|group| group.as_str().to_string())
+                    .unwrap_or_default(),
+            ),
+            None => (false, file_name),
+        };
+        groups.entry(group).or_default().push((path, label));
+    }
+    if groups.is_empty() {
+        return Err("No wav records found in ".to_owned() + data_dir);
+    }
+    // groups are sorted by name before shuffling, so the seed produces the same split on every run
+    let mut groups = groups.into_values().collect::<Vec<_>>();
+    groups.shuffle(&mut StdRng::seed_from_u64(seed));
+    let mut split = DataSplit {
+        train: Vec::new(),
+        test: Vec::new(),
+    };
+    let mut test_counts: BTreeMap<String, usize> = BTreeMap::new();
+    for group in groups {
+        let mut group_counts: BTreeMap<&str, usize> = BTreeMap::new();
+        for (_, label) in &group {
+            *group_counts.entry(label).or_default() += 1;
+        }
+        let (distance, test_distance) =
+            group_counts
+                .iter()
+                .fold((0., 0.), |(distance, test_distance), (label, count)| {
+                    let target = label_counts[*label] as f32 * settings.test_ratio;
+                    let test_count = test_counts.get(*label).copied().unwrap_or_default() as f32;
+                    (
+                        distance + (target - test_count).abs(),
+                        test_distance + (target - test_count - *count as f32).abs(),
+                    )
+                });
+        if test_distance < distance {
+            for (label, count) in group_counts {
+                *test_counts.entry(label.to_string()).or_default() += count;
+            }
+            split.test.extend(group.into_iter().map(|(path, _)| path));
+        } else {
+            split.train.extend(group.into_iter().map(|(path, _)| path));
+        }
+    }
+    split.train.sort();
+    split.test.sort();
+    for (label, count) in &label_counts {
+        let test_count = test_counts.get(label).copied().unwrap_or_default();
+        println!(
+            "{}: {} train records, {} test records.",
+            label,
+            count - test_count,
+            test_count
+        );
+        if test_count == *count {
+            return Err(format!(
+                "Label {} has no train records, check the groups or lower the test ratio",
+                label
+            ));
+        }
+        if test_count == 0 {
+            println!("Warning: label {} has no test records.", label);
+        }
+    }
+    Ok(split)
+}
+impl DataSplit {
+    /// Writes the split manifest.
+    pub(crate) fn write_manifest(
+        &self,
+        manifest_path: &Path,
+        data_dir: &str,
+        settings: &SplitSettings,
+        seed: u64,
+    ) -> Result<(), String> {
+        let manifest = SplitManifest {
+            data_dir,
+            test_ratio: settings.test_ratio,
+            group_by: settings.group_by.as_ref().map(Regex::as_str),
+            seed,
+            train: self.train.iter().map(|path| get_file_name(path)).collect(),
+            test: self.test.iter().map(|path| get_file_name(path)).collect(),
+        };
+        let file = File::create(manifest_path).map_err(|err| err.to_string())?;
+        serde_json::to_writer_pretty(file, &manifest).map_err(|err| err.to_string())
+    }
+}
+/// Returns the record label, the text between the first "[" and the next "]" in lowercase, as the training does.
+pub(crate) fn get_label(file_name: &str) -> String {
+    let label = file_name.split_once('[').and_then(|(prefix, rest)| {
+        // the closing delimiter is searched from the start, like the training does
+        rest.split_once(']')
+            .filter(|_| !prefix.contains(']'))
+            .map(|(label, _)| label.to_lowercase())
+    });
+    label.unwrap_or_else(|| NONE_LABEL.to_string())
+}
+fn get_file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+fn parse_regex(value: &str) -> Result<Regex, String> {
+    Regex::new(value).map_err(|err| err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Creates an empty directory with empty wav files, the split only looks at their names.
+    fn create_data_dir(name: &str, file_names: &[String]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "rustpotter-cli-split-{}-{}",
+            name,
+            std::process::id()
+        ));
+        _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for file_name in file_names {
+            File::create(dir.join(file_name)).unwrap();
+        }
+        dir
+    }
+    fn split(
+        dir: &Path,
+        test_ratio: f32,
+        group_by: Option<&str>,
+        seed: u64,
+    ) -> Result<DataSplit, String> {
+        let settings = SplitSettings {
+            data_dir: None,
+            test_ratio,
+            group_by: group_by.map(|group_by| Regex::new(group_by).unwrap()),
+        };
+        split_data_dir(
+            &dir.to_string_lossy(),
+            &settings,
+            seed,
+            &ProvenanceFilter::default(),
+        )
+    }
+    fn count_label(paths: &[String], label: &str) -> usize {
+        paths
+            .iter()
+            .filter(|path| get_label(&get_file_name(path)) == label)
+            .count()
+    }
+
+    #[test]
+    fn get_label_reads_the_first_tag() {
+        assert_eq!(get_label("[Ok_Home]1.wav"), "ok_home");
+        assert_eq!(get_label("speaker1_[hey]2.wav"), "hey");
+        assert_eq!(get_label("[hey][none]3.wav"), "hey");
+        assert_eq!(get_label("[none]4.wav"), NONE_LABEL);
+        assert_eq!(get_label("5.wav"), NONE_LABEL);
+        assert_eq!(get_label("[hey6.wav"), NONE_LABEL);
+        assert_eq!(get_label("x]y[hey]7.wav"), NONE_LABEL);
+    }
+
+    #[test]
+    fn split_keeps_the_label_proportions() {
+        let file_names = (0..20)
+            .map(|index| format!("[hey]{}.wav", index))
+            .chain((0..40).map(|index| format!("{}.wav", index)))
+            .collect::<Vec<_>>();
+        let dir = create_data_dir("ratio", &file_names);
+        let data_split = split(&dir, 0.25, None, 1).unwrap();
+        assert_eq!(count_label(&data_split.test, "hey"), 5);
+        assert_eq!(count_label(&data_split.test, NONE_LABEL), 10);
+        assert_eq!(data_split.train.len() + data_split.test.len(), 60);
+        assert!(data_split
+            .train
+            .iter()
+            .all(|path| !data_split.test.contains(path)));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn split_depends_only_on_the_seed() {
+        let file_names = (0..30)
+            .map(|index| format!("[hey]{}.wav", index))
+            .collect::<Vec<_>>();
+        let dir = create_data_dir("seed", &file_names);
+        let first = split(&dir, 0.2, None, 7).unwrap();
+        let second = split(&dir, 0.2, None, 7).unwrap();
+        let other = split(&dir, 0.2, None, 8).unwrap();
+        assert_eq!(first.test, second.test);
+        assert_eq!(first.train, second.train);
+        assert_ne!(first.test, other.test);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn split_keeps_the_groups_together() {
+        let file_names = (0..6)
+            .flat_map(|speaker| {
+                (0..4).flat_map(move |index| {
+                    [
+                        format!("speaker{}_[hey]{}.wav", speaker, index),
+                        format!("speaker{}_{}.wav", speaker, index),
+                    ]
+                })
+            })
+            .collect::<Vec<_>>();
+        let dir = create_data_dir("groups", &file_names);
+        let data_split = split(&dir, 0.34, Some(r"^(speaker\d+)_"), 3).unwrap();
+        assert!(!data_split.test.is_empty());
+        for speaker in 0..6 {
+            let prefix = format!("speaker{}_", speaker);
+            let test_records = data_split
+                .test
+                .iter()
+                .filter(|path| get_file_name(path).starts_with(&prefix))
+                .count();
+            assert!(
+                test_records == 0 || test_records == 8,
+                "{} was split",
+                prefix
+            );
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn split_rejects_invalid_settings() {
+        let dir = create_data_dir("invalid", &["[hey]0.wav".to_string()]);
+        assert!(split(&dir, 0., None, 1).is_err());
+        assert!(split(&dir, 1., None, 1).is_err());
+        // the only record of the label goes to the test set
+        assert!(split(&dir, 0.9, None, 1).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(split(&dir, 0.5, None, 1).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
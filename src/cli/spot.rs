@@ -1,5 +1,6 @@
-use std::{sync::mpsc, time::SystemTime};
+use std::{collections::HashMap, sync::mpsc, time::SystemTime};
 
+use crate::cli::provenance::{get_sidecar_path, Provenance, ProvenanceArgs};
 use crate::cli::record::{self, is_compatible_buffer_size};
 use clap::Args;
 use cpal::{
@@ -9,9 +10,16 @@ use cpal::{
 use gag::Gag;
 use rustpotter::{
     Rustpotter, RustpotterConfig, RustpotterDetection, Sample, SampleFormat, ScoreMode, VADMode,
+    WakewordLoad, WakewordModel, WakewordRef,
 };
+use serde::Deserialize;
 use time::OffsetDateTime;
 
+/// Audio duration between consecutive mfcc frames.
+pub(crate) const MFCC_FRAME_SHIFT_MS: usize = 10;
+/// Label used by the wakeword models for the non wakeword audio.
+const NN_NONE_LABEL: &str = "none";
+
 #[derive(Args, Debug)]
 /// Spot wakewords.
 #[clap()]
@@ -89,6 +97,8 @@ pub struct SpotCommand {
     #[clap(short, long)]
     /// Path to create records, one on the first partial detection and another each one that scores better.
     record_path: Option<String>,
+    #[clap(flatten)]
+    provenance: ProvenanceArgs,
 }
 
 pub fn spot(command: SpotCommand) -> Result<(), String> {
@@ -119,6 +129,23 @@ pub fn spot(command: SpotCommand) -> Result<(), String> {
     if let Some(stderr_gag) = stderr_gag {
         drop(stderr_gag);
     }
+    let record_sidecars = command
+        .record_path
+        .as_ref()
+        .map(|record_path| RecordSidecars {
+            record_path: record_path.clone(),
+            provenance: Provenance::new(
+                host_name,
+                &device,
+                &device_config,
+                1.,
+                &command.provenance,
+            ),
+            start_ms: std::time::UNIX_EPOCH
+                .elapsed()
+                .map_or(0, |elapsed| elapsed.as_millis()),
+            partial_detection_score: None,
+        });
     let bits_per_sample = (device_config.sample_format().sample_size() * 8) as u16;
     // configure rustpotter
     let mut config = RustpotterConfig::default();
@@ -137,7 +164,7 @@ pub fn spot(command: SpotCommand) -> Result<(), String> {
     config.detector.score_mode = command.score_mode;
     config.detector.score_ref = command.score_ref;
     config.detector.vad_mode = command.vad_mode;
-    config.detector.record_path = command.record_path;
+    config.detector.record_path = command.record_path.clone();
     config.filters.gain_normalizer.enabled = command.gain_normalizer;
     config.filters.gain_normalizer.gain_ref = command.gain_ref;
     config.filters.gain_normalizer.min_gain = command.min_gain;
@@ -171,10 +198,7 @@ pub fn spot(command: SpotCommand) -> Result<(), String> {
     } else {
         None
     };
-    for path in command.model_path {
-        println!("Loading wakeword file: {}", path);
-        rustpotter.add_wakeword_from_file("w", &path)?;
-    }
+    let wakeword_files = load_wakewords(&mut rustpotter, &command.model_path, true)?;
     if command.debug_gain {
         println!(
             "Gain Normalizer RMS level reference: {}",
@@ -191,40 +215,40 @@ pub fn spot(command: SpotCommand) -> Result<(), String> {
     if command.debug {
         println!("Audio stream config: {:?}", stream_config);
     }
-    let buffer_i8: Vec<i16> = Vec::new();
-    let buffer_i16: Vec<i16> = Vec::new();
-    let buffer_i32: Vec<i32> = Vec::new();
-    let buffer_f32: Vec<f32> = Vec::new();
     let stream = match device_config.sample_format() {
-        cpal::SampleFormat::I8 => init_spot_stream(
+        cpal::SampleFormat::I8 => init_spot_stream::<i16>(
             &device,
             &stream_config,
             rustpotter,
-            buffer_i8,
+            wakeword_files,
+            record_sidecars,
             command.debug,
             command.debug_gain,
         )?,
-        cpal::SampleFormat::I16 => init_spot_stream(
+        cpal::SampleFormat::I16 => init_spot_stream::<i16>(
             &device,
             &stream_config,
             rustpotter,
-            buffer_i16,
+            wakeword_files,
+            record_sidecars,
             command.debug,
             command.debug_gain,
         )?,
-        cpal::SampleFormat::I32 => init_spot_stream(
+        cpal::SampleFormat::I32 => init_spot_stream::<i32>(
             &device,
             &stream_config,
             rustpotter,
-            buffer_i32,
+            wakeword_files,
+            record_sidecars,
             command.debug,
             command.debug_gain,
         )?,
-        cpal::SampleFormat::F32 => init_spot_stream(
+        cpal::SampleFormat::F32 => init_spot_stream::<f32>(
             &device,
             &stream_config,
             rustpotter,
-            buffer_f32,
+            wakeword_files,
+            record_sidecars,
             command.debug,
             command.debug_gain,
         )?,
@@ -241,29 +265,82 @@ pub fn spot(command: SpotCommand) -> Result<(), String> {
     Ok(())
 }
 
+/// Writes the sidecar of the records created by the detector, as they are created.
+struct RecordSidecars {
+    record_path: String,
+    provenance: Provenance,
+    /// Records named with an older time are not from this run.
+    start_ms: u128,
+    partial_detection_score: Option<f32>,
+}
+impl RecordSidecars {
+    /// Checks the detector state after processing an audio block,
+    /// the detector creates a record each time the partial detection improves.
+    fn update(&mut self, rustpotter: &Rustpotter) {
+        let partial_detection = rustpotter.get_partial_detection();
+        let partial_detection_score = partial_detection.map(|detection| detection.score);
+        if partial_detection_score == self.partial_detection_score {
+            return;
+        }
+        self.partial_detection_score = partial_detection_score;
+        if let Some(partial_detection) = partial_detection {
+            self.provenance.set_gain(partial_detection.gain);
+        }
+        // also checked when the partial detection ends, as it can improve and end on the same block
+        let Ok(entries) = std::fs::read_dir(&self.record_path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let is_new_record = get_record_timestamp(&entry.file_name().to_string_lossy())
+                .is_some_and(|timestamp| timestamp >= self.start_ms);
+            let path = entry.path().to_string_lossy().to_string();
+            if is_new_record && !get_sidecar_path(&path).exists() {
+                if let Err(err) = self.provenance.write_sidecar(&path) {
+                    eprintln!("Unable to write the sidecar of {}: {}", path, err);
+                }
+            }
+        }
+    }
+}
+/// Returns the creation time, in milliseconds since the unix epoch, of a record created by the detector,
+/// which are named "[wakeword]time-score.wav", with the score decimal point replaced by an underscore.
+fn get_record_timestamp(file_name: &str) -> Option<u128> {
+    let (name_and_timestamp, score) = file_name.strip_suffix(".wav")?.rsplit_once('-')?;
+    score.replace('_', ".").parse::<f32>().ok()?;
+    let (name, timestamp) = name_and_timestamp.strip_prefix('[')?.rsplit_once(']')?;
+    if name.is_empty() || !timestamp.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    timestamp.parse().ok()
+}
+
 fn init_spot_stream<S: Sample + SizedSample>(
     device: &cpal::Device,
     stream_config: &cpal::StreamConfig,
     mut rustpotter: Rustpotter,
-    mut buffer: Vec<S>,
+    wakeword_files: WakewordFiles,
+    mut record_sidecars: Option<RecordSidecars>,
     debug: bool,
     debug_gain: bool,
 ) -> Result<cpal::Stream, String> {
     let error_callback = move |err| {
         eprintln!("an error occurred on stream: {}", err);
     };
+    let mut buffer: Vec<S> = Vec::new();
     let mut partial_detection_counter = 0;
-    let rustpotter_samples_per_frame = rustpotter.get_samples_per_frame();
     let data_callback = move |data: &[S], _: &_| {
         run_detection(
             &mut rustpotter,
+            &wakeword_files,
             data,
             &mut buffer,
-            rustpotter_samples_per_frame,
             &mut partial_detection_counter,
             debug,
             debug_gain,
-        )
+        );
+        if let Some(record_sidecars) = record_sidecars.as_mut() {
+            record_sidecars.update(&rustpotter);
+        }
     };
     device
         .build_input_stream(stream_config, data_callback, error_callback, None)
@@ -272,23 +349,19 @@ fn init_spot_stream<S: Sample + SizedSample>(
 
 fn run_detection<T: Sample>(
     rustpotter: &mut Rustpotter,
+    wakeword_files: &WakewordFiles,
     data: &[T],
     buffer: &mut Vec<T>,
-    rustpotter_samples_per_frame: usize,
     partial_detection_counter: &mut usize,
     debug: bool,
     debug_gain: bool,
 ) {
-    buffer.extend_from_slice(data);
-    while buffer.len() >= rustpotter_samples_per_frame {
-        let detection = rustpotter.process_samples(
-            buffer
-                .drain(0..rustpotter_samples_per_frame)
-                .as_slice()
-                .into(),
-        );
+    let rustpotter_samples_per_frame = rustpotter.get_samples_per_frame();
+    for frame in drain_frames(buffer, data, rustpotter_samples_per_frame) {
+        let detection = rustpotter.process_samples(frame);
         print_detection(
             &*rustpotter,
+            wakeword_files,
             detection,
             partial_detection_counter,
             debug,
@@ -298,8 +371,25 @@ fn run_detection<T: Sample>(
     }
 }
 
+/// Appends the input data to the buffer and returns an iterator over the complete detector frames.
+pub(crate) fn drain_frames<'a, T: Sample>(
+    buffer: &'a mut Vec<T>,
+    data: &[T],
+    samples_per_frame: usize,
+) -> impl Iterator<Item = Vec<T>> + 'a {
+    buffer.extend_from_slice(data);
+    std::iter::from_fn(move || {
+        if buffer.len() >= samples_per_frame {
+            Some(buffer.drain(0..samples_per_frame).collect())
+        } else {
+            None
+        }
+    })
+}
+
 pub(crate) fn print_detection(
     rustpotter: &Rustpotter,
+    wakeword_files: &WakewordFiles,
     detection: Option<RustpotterDetection>,
     partial_detection_counter: &mut usize,
     debug: bool,
@@ -316,7 +406,12 @@ pub(crate) fn print_detection(
     let partial_detection = rustpotter.get_partial_detection();
     *partial_detection_counter = match detection {
         Some(detection) => {
-            println!("Wakeword detection: [{}] {:?}", time_getter(), detection);
+            println!(
+                "Wakeword detection: [{}] ({}) {:?}",
+                time_getter(),
+                wakeword_files.get_path(&detection.name),
+                detection
+            );
             0
         }
         None => partial_detection.map_or_else(
@@ -335,7 +430,202 @@ pub(crate) fn print_detection(
         ),
     };
 }
+/// Relates the loaded wakeword names with the files they were loaded from.
+pub(crate) struct WakewordFiles {
+    paths: HashMap<String, String>,
+    thresholds: HashMap<String, f32>,
+    max_mfcc_frames: usize,
+}
+impl WakewordFiles {
+    /// Returns the file the detected wakeword was loaded from.
+    pub(crate) fn get_path(&self, name: &str) -> &str {
+        self.paths.get(name).map_or("unknown", String::as_str)
+    }
+    /// Returns the sorted names of the loaded wakewords.
+    pub(crate) fn get_names(&self) -> Vec<String> {
+        let mut names = self.paths.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+    /// Returns the threshold the detector applies to the wakeword, its file one or the provided default.
+    pub(crate) fn get_threshold(&self, name: &str, default: f32) -> f32 {
+        self.thresholds.get(name).copied().unwrap_or(default)
+    }
+    /// Returns the audio duration covered by the longer wakeword, used to estimate where a detection starts.
+    pub(crate) fn get_duration_ms(&self) -> usize {
+        self.max_mfcc_frames * MFCC_FRAME_SHIFT_MS
+    }
+}
+/// Adds the wakeword files to the detector, using its paths as keys.
+pub(crate) fn load_wakewords(
+    rustpotter: &mut Rustpotter,
+    model_paths: &[String],
+    log: bool,
+) -> Result<WakewordFiles, String> {
+    let mut wakeword_files = WakewordFiles {
+        paths: HashMap::new(),
+        thresholds: HashMap::new(),
+        max_mfcc_frames: 0,
+    };
+    for path in model_paths {
+        if log {
+            println!("Loading wakeword file: {}", path);
+        }
+        rustpotter.add_wakeword_from_file(path, path)?;
+        let wakeword_info = read_wakeword_info(path)?;
+        wakeword_files.max_mfcc_frames = wakeword_files
+            .max_mfcc_frames
+            .max(wakeword_info.mfcc_frames);
+        for name in wakeword_info.names {
+            if let Some(threshold) = wakeword_info.threshold {
+                wakeword_files.thresholds.insert(name.clone(), threshold);
+            }
+            if let Some(other_path) = wakeword_files.paths.insert(name.clone(), path.clone()) {
+                println!(
+                    "Warning: wakeword '{}' is defined in {} and {}, its detections can not be attributed to a file.",
+                    name, other_path, path
+                );
+            }
+        }
+    }
+    Ok(wakeword_files)
+}
+/// Wakeword file details, read without loading it into a detector.
+pub(crate) struct WakewordInfo {
+    /// Names of the detections it can emit.
+    pub names: Vec<String>,
+    /// Number of mfcc frames of its longer sample or of its model input.
+    pub mfcc_frames: usize,
+    /// Reference or model type.
+    pub kind: String,
+    /// Number of samples, for references.
+    pub samples: Option<usize>,
+    /// Detection threshold stored in the file, for references.
+    pub threshold: Option<f32>,
+}
+/// Wakeword reference format of the v2 releases, that the detector still accepts.
+#[derive(Deserialize)]
+struct WakewordV2 {
+    name: String,
+    avg_features: Option<Vec<Vec<f32>>>,
+    samples_features: HashMap<String, Vec<Vec<f32>>>,
+    threshold: Option<f32>,
+    avg_threshold: Option<f32>,
+    rms_level: f32,
+    /// Only present in this format, so the references are not read as it.
+    #[serde(rename = "enabled")]
+    _enabled: bool,
+}
+impl WakewordLoad for WakewordV2 {}
+impl From<WakewordV2> for WakewordRef {
+    fn from(wakeword: WakewordV2) -> Self {
+        WakewordRef {
+            name: wakeword.name,
+            mfcc_size: wakeword
+                .samples_features
+                .values()
+                .next()
+                .and_then(|features| features.first())
+                .map_or(0, |frame| frame.len() as u16),
+            threshold: wakeword.threshold,
+            avg_threshold: wakeword.avg_threshold,
+            avg_features: wakeword.avg_features,
+            samples_features: wakeword.samples_features,
+            rms_level: wakeword.rms_level,
+        }
+    }
+}
+/// Adds the wakeword file content to the detector without the thresholds stored in it,
+/// so the detector ones apply, trying the formats in the same order as the detector.
+pub(crate) fn add_wakeword_without_thresholds(
+    rustpotter: &mut Rustpotter,
+    key: &str,
+    buffer: &[u8],
+) -> Result<(), String> {
+    WakewordV2::load_from_buffer(buffer)
+        .map(WakewordRef::from)
+        .or_else(|_| WakewordRef::load_from_buffer(buffer))
+        .and_then(|mut wakeword| {
+            wakeword.threshold = None;
+            wakeword.avg_threshold = None;
+            rustpotter.add_wakeword_ref(key, wakeword)
+        })
+        .or_else(|_| {
+            WakewordModel::load_from_buffer(buffer)
+                .and_then(|wakeword| rustpotter.add_wakeword_model(key, wakeword))
+        })
+}
+/// Reads the wakeword file details, trying the formats in the same order as the detector.
+pub(crate) fn read_wakeword_info(path: &str) -> Result<WakewordInfo, String> {
+    let get_reference_info = |name: String,
+                              samples_features: HashMap<String, Vec<Vec<f32>>>,
+                              threshold: Option<f32>,
+                              kind: &str| WakewordInfo {
+        names: vec![name],
+        mfcc_frames: samples_features.values().map(Vec::len).max().unwrap_or(0),
+        kind: kind.to_string(),
+        samples: Some(samples_features.len()),
+        threshold,
+    };
+    WakewordV2::load_from_file(path)
+        .map(|wakeword| {
+            get_reference_info(
+                wakeword.name,
+                wakeword.samples_features,
+                wakeword.threshold,
+                "reference (v2)",
+            )
+        })
+        .or_else(|_| {
+            WakewordRef::load_from_file(path).map(|wakeword| {
+                get_reference_info(
+                    wakeword.name,
+                    wakeword.samples_features,
+                    wakeword.threshold,
+                    "reference",
+                )
+            })
+        })
+        .or_else(|_| {
+            WakewordModel::load_from_file(path).map(|wakeword| WakewordInfo {
+                names: wakeword
+                    .labels
+                    .into_iter()
+                    .filter(|label| label != NN_NONE_LABEL)
+                    .collect(),
+                mfcc_frames: wakeword.train_size,
+                kind: wakeword.m_type.as_str().to_string(),
+                samples: None,
+                threshold: None,
+            })
+        })
+}
 fn get_time_string() -> String {
     let dt: OffsetDateTime = SystemTime::now().into();
     format!("{:02}:{:02}:{:02}", dt.hour(), dt.minute(), dt.second())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_record_timestamp_matches_the_detection_records() {
+        assert_eq!(
+            get_record_timestamp("[ok home]1700000000123-0_61234.wav"),
+            Some(1_700_000_000_123)
+        );
+        assert_eq!(
+            get_record_timestamp("[a]b]1700000000123-1.wav"),
+            Some(1_700_000_000_123)
+        );
+    }
+
+    #[test]
+    fn get_record_timestamp_ignores_other_wav_files() {
+        assert_eq!(get_record_timestamp("take-1.wav"), None);
+        assert_eq!(get_record_timestamp("[none]noise-250ms-0_512.wav"), None);
+        assert_eq!(get_record_timestamp("[]1700000000123-0_5.wav"), None);
+        assert_eq!(get_record_timestamp("[ok]1700000000123-0_5.json"), None);
+    }
+}
@@ -1,187 +1,693 @@
 use clap::Args;
-use hound::{SampleFormat, WavReader};
+use hound::{SampleFormat, WavReader, WavSpec};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rustpotter::{Rustpotter, RustpotterConfig, Sample, ScoreMode, VADMode};
-use std::{fs::File, io::BufReader};
+use serde::{Deserialize, Deserializer};
+use std::{
+    convert::Infallible,
+    fs::File,
+    io::BufReader,
+    ops::Range,
+    str::FromStr,
+    time::{Duration, Instant},
+};
 
-use super::spot::print_detection;
+use super::{
+    audio::{get_noise_segment, mix_noise, read_noises, read_samples},
+    spot::{drain_frames, load_wakewords, print_detection, WakewordFiles},
+    trace::{DetectionTrace, FrameScorer},
+};
+
+/// Files longer than this report the processing progress on stderr.
+const PROGRESS_MIN_DURATION_SECS: usize = 300;
 
 #[derive(Args, Debug)]
-/// Test wakeword file against a wav sample, detector is automatically configured according to the sample spec
+/// Test wakeword files against a wav sample, detector is automatically configured according to the sample spec
 #[clap()]
 pub struct TestCommand {
-    #[clap()]
-    /// Model to test.
-    model_path: String,
+    #[clap(num_args = 1.., required = true)]
+    /// Model path list.
+    model_path: Vec<String>,
     #[clap()]
     /// Wav record to test.
     sample_path: String,
+    #[clap(flatten)]
+    detector: DetectorSettings,
+    #[clap(short, long)]
+    /// Log partial detections.
+    debug: bool,
+    #[clap(long)]
+    /// Log rms level ref, gain applied per frame and frame rms level.
+    debug_gain: bool,
+    #[clap(short, long)]
+    /// Path to create records, one on the first partial detection and another each one that scores better.
+    record_path: Option<String>,
+    #[clap(long, default_value_t = 3000)]
+    /// Milliseconds of silence appended after the record, so a detection at the end of the file can be emitted.
+    tail_ms: usize,
+    #[clap(long, value_parser = parse_time_offset)]
+    /// Start evaluating the record at this offset ("[[hh:]mm:]ss[.mmm]").
+    from: Option<usize>,
+    #[clap(long, value_parser = parse_time_offset)]
+    /// Stop evaluating the record at this offset ("[[hh:]mm:]ss[.mmm]").
+    to: Option<usize>,
+    #[clap(long)]
+    /// Write a csv file with the time, rms level, gain, partial detections counter and wakeword scores of each frame.
+    /// Scores are computed for every frame with threshold 0, empty until the wakeword duration has been processed.
+    /// Each wakeword file is scored on its own detectors, so its scores are not hidden by the other files.
+    trace: Option<String>,
+    #[clap(long)]
+    /// Render a svg timeline with the audio envelope, the wakeword scores, the thresholds and the detections.
+    plot: Option<String>,
+    #[clap(long)]
+    /// Directory containing wav records of noise, a random segment of one of them is mixed into the record.
+    noise_dir: Option<String>,
+    #[clap(
+        long,
+        default_value_t = 10.,
+        allow_hyphen_values = true,
+        requires = "noise_dir"
+    )]
+    /// Signal to noise ratio, in decibels, of the mixed noise.
+    snr_db: f32,
+    #[clap(long)]
+    /// Seed used to choose the noise segment and the random buffer sizes, a random one is used and printed if unset.
+    seed: Option<u64>,
+    #[clap(long, alias = "simulate-device-buffer")]
+    /// Feed the record in blocks of this number of frames, like an audio device does in the "spot" command.
+    /// Accepts a fixed size ("512") or a random size per block ("random:256-2048").
+    simulate_buffer: Option<BufferSimulation>,
+    #[clap(long)]
+    /// Feed the record at wall-clock speed.
+    realtime: bool,
+}
+
+#[derive(Args, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+/// Detector and audio filters options, shared by the commands that run the detection over wav files.
+pub(crate) struct DetectorSettings {
     #[clap(short, long, default_value_t = 0.5)]
     /// Default detection threshold, only applies to models without threshold.
-    threshold: f32,
+    pub threshold: f32,
     #[clap(short, long, default_value_t = 0.2)]
     /// Default detection averaged threshold, only applies to models without averaged threshold.
-    averaged_threshold: f32,
+    pub averaged_threshold: f32,
     #[clap(short, long, default_value_t = 10)]
     /// Minimum number of partial detections
-    min_scores: usize,
+    pub min_scores: usize,
     #[clap(short, long)]
     /// Emit detection on min scores.
-    eager: bool,
+    pub eager: bool,
     #[clap(short = 's', long, default_value_t = ScoreMode::Max)]
+    #[serde(deserialize_with = "deserialize_from_str")]
     /// How to calculate a unified score, no applies to wakeword models.
-    score_mode: ScoreMode,
+    pub score_mode: ScoreMode,
     #[clap(short = 'v', long)]
+    #[serde(deserialize_with = "deserialize_option_from_str")]
     /// Enabled vad detection.
-    vad_mode: Option<VADMode>,
+    pub vad_mode: Option<VADMode>,
     #[clap(short = 'g', long)]
     /// Enables a gain-normalizer audio filter.
-    gain_normalizer: bool,
+    pub gain_normalizer: bool,
     #[clap(long, default_value_t = 0.1)]
     /// Min gain applied by the gain-normalizer filter.
-    min_gain: f32,
+    pub min_gain: f32,
     #[clap(long, default_value_t = 1.)]
     /// Max gain applied by the gain-normalizer filter.
-    max_gain: f32,
+    pub max_gain: f32,
     #[clap(long)]
     /// Set the rms level reference used by the gain normalizer filter.
     /// If unset the max wakeword rms level is used.
-    gain_ref: Option<f32>,
+    pub gain_ref: Option<f32>,
     #[clap(short, long)]
     /// Enables a band-pass audio filter.
-    band_pass: bool,
+    pub band_pass: bool,
     #[clap(long, default_value_t = 80.)]
     /// Band-pass audio filter low cutoff.
-    low_cutoff: f32,
+    pub low_cutoff: f32,
     #[clap(long, default_value_t = 400.)]
     /// Band-pass audio filter high cutoff.
-    high_cutoff: f32,
+    pub high_cutoff: f32,
     #[clap(long, default_value_t = 0.22)]
     /// Used to express the score as value in range 0 - 1.
-    score_ref: f32,
-    #[clap(short, long)]
-    /// Log partial detections.
-    debug: bool,
-    #[clap(long)]
-    /// Log rms level ref, gain applied per frame and frame rms level.
-    debug_gain: bool,
-    #[clap(short, long)]
-    /// Path to create records, one on the first partial detection and another each one that scores better.
-    record_path: Option<String>,
+    pub score_ref: f32,
 }
+impl Default for DetectorSettings {
+    fn default() -> Self {
+        DetectorSettings {
+            threshold: 0.5,
+            averaged_threshold: 0.2,
+            min_scores: 10,
+            eager: false,
+            score_mode: ScoreMode::Max,
+            vad_mode: None,
+            gain_normalizer: false,
+            min_gain: 0.1,
+            max_gain: 1.,
+            gain_ref: None,
+            band_pass: false,
+            low_cutoff: 80.,
+            high_cutoff: 400.,
+            score_ref: 0.22,
+        }
+    }
+}
+impl DetectorSettings {
+    pub(crate) fn apply(&self, config: &mut RustpotterConfig) {
+        config.detector.avg_threshold = self.averaged_threshold;
+        config.detector.threshold = self.threshold;
+        config.detector.min_scores = self.min_scores;
+        config.detector.eager = self.eager;
+        config.detector.score_mode = self.score_mode;
+        config.detector.score_ref = self.score_ref;
+        config.detector.vad_mode = self.vad_mode;
+        config.filters.gain_normalizer.enabled = self.gain_normalizer;
+        config.filters.gain_normalizer.gain_ref = self.gain_ref;
+        config.filters.gain_normalizer.min_gain = self.min_gain;
+        config.filters.gain_normalizer.max_gain = self.max_gain;
+        config.filters.band_pass.enabled = self.band_pass;
+        config.filters.band_pass.low_cutoff = self.low_cutoff;
+        config.filters.band_pass.high_cutoff = self.high_cutoff;
+    }
+}
+fn deserialize_from_str<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(serde::de::Error::custom)
+}
+fn deserialize_option_from_str<'de, D: Deserializer<'de>, T: FromStr<Err = String>>(
+    deserializer: D,
+) -> Result<Option<T>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| value.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// Size of the audio blocks used to feed the detector.
+#[derive(Clone, Debug)]
+pub(crate) enum BufferSimulation {
+    Fixed(usize),
+    Random(usize, usize),
+}
+impl BufferSimulation {
+    fn next_size(&self, rng: &mut StdRng) -> usize {
+        match *self {
+            BufferSimulation::Fixed(size) => size,
+            BufferSimulation::Random(min, max) => rng.gen_range(min..=max),
+        }
+    }
+}
+impl std::str::FromStr for BufferSimulation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid_value = || format!("Invalid buffer size '{}'", s);
+        let parse_size = |size: &str| {
+            size.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|size| *size > 0)
+                .ok_or_else(invalid_value)
+        };
+        match s.strip_prefix("random:") {
+            Some(range) => {
+                let (min, max) = range.split_once('-').ok_or_else(invalid_value)?;
+                let (min, max) = (parse_size(min)?, parse_size(max)?);
+                if min > max {
+                    return Err(invalid_value());
+                }
+                Ok(BufferSimulation::Random(min, max))
+            }
+            None => Ok(BufferSimulation::Fixed(parse_size(s)?)),
+        }
+    }
+}
+
+/// Options used to run the detection over a wav file.
+#[derive(Default)]
+pub(crate) struct RunOptions {
+    /// Offset to start at, in milliseconds.
+    pub from_ms: Option<usize>,
+    /// Offset to stop at, in milliseconds.
+    pub to_ms: Option<usize>,
+    /// Silence appended after the evaluated audio, in milliseconds.
+    pub tail_ms: usize,
+    /// Frames per block, the detector frame size is used when unset.
+    pub buffer_simulation: Option<BufferSimulation>,
+    /// Seed of the random block sizes.
+    pub seed: u64,
+    pub realtime: bool,
+    pub trace_path: Option<String>,
+    pub plot_path: Option<String>,
+    pub record_path: Option<String>,
+    /// Do not log the detections.
+    pub quiet: bool,
+    pub debug: bool,
+    pub debug_gain: bool,
+}
+
+/// Detection found while running the detector over a wav file.
+pub(crate) struct FileDetection {
+    pub name: String,
+    pub model_path: String,
+    pub start_ms: usize,
+    pub end_ms: usize,
+    pub score: f32,
+}
+
 pub fn test(command: TestCommand) -> Result<(), String> {
     println!(
-        "Testing file {} against model {}!",
+        "Testing file {} against models {:?}!",
         command.sample_path, command.model_path,
     );
+    let seed = command.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    if let Some(BufferSimulation::Random(min, max)) = command.simulate_buffer {
+        println!(
+            "Simulating device buffers of {} to {} frames, seed {}",
+            min, max, seed
+        );
+    }
+    let options = RunOptions {
+        from_ms: command.from,
+        to_ms: command.to,
+        tail_ms: command.tail_ms,
+        buffer_simulation: command.simulate_buffer,
+        seed,
+        realtime: command.realtime,
+        trace_path: command.trace,
+        plot_path: command.plot,
+        record_path: command.record_path,
+        quiet: false,
+        debug: command.debug,
+        debug_gain: command.debug_gain,
+    };
+    match command.noise_dir.as_ref() {
+        Some(noise_dir) => {
+            let noises = read_noises(noise_dir)?;
+            println!(
+                "Mixing noise from {} records at snr {}dB, seed {}",
+                noises.len(),
+                command.snr_db,
+                seed
+            );
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (wav_specs, samples) = read_samples(&command.sample_path)?;
+            let channels = wav_specs.channels as usize;
+            let time_range = get_sample_range(
+                wav_specs.sample_rate as usize,
+                samples.len() / channels,
+                options.from_ms,
+                options.to_ms,
+            )?;
+            let noise = &noises[rng.gen_range(0..noises.len())];
+            let noise_segment = get_noise_segment(
+                noise,
+                wav_specs.sample_rate as usize,
+                time_range.len(),
+                &mut rng,
+            );
+            let samples = mix_noise(
+                &samples[time_range.start * channels..time_range.end * channels],
+                wav_specs.channels,
+                &noise_segment,
+                command.snr_db,
+            );
+            run_samples_detection(
+                samples.into_iter().map(Ok::<f32, Infallible>),
+                &wav_specs,
+                time_range,
+                &command.model_path,
+                &command.detector,
+                &options,
+            )?;
+        }
+        None => {
+            run_file_detection(
+                &command.sample_path,
+                &command.model_path,
+                &command.detector,
+                &options,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns an error when the wav file can not be opened or has a sample format the detection does not support.
+pub(crate) fn check_wav_file(sample_path: &str) -> Result<(), String> {
+    let wav_reader = WavReader::open(sample_path)
+        .map_err(|err| "Unable to open file ".to_owned() + sample_path + ": " + &err.to_string())?;
+    let wav_specs = wav_reader.spec();
+    match (wav_specs.sample_format, wav_specs.bits_per_sample) {
+        (SampleFormat::Int, 8 | 16 | 32) | (SampleFormat::Float, 32) => Ok(()),
+        _ => Err("Unsupported wav format".to_string()),
+    }
+}
+/// Runs the detector over a wav file, returns the detections found.
+pub(crate) fn run_file_detection(
+    sample_path: &str,
+    model_paths: &[String],
+    settings: &DetectorSettings,
+    options: &RunOptions,
+) -> Result<Vec<FileDetection>, String> {
     // Read wav file
-    let file_reader =
-        BufReader::new(File::open(command.sample_path).map_err(|err| err.to_string())?);
+    let file_reader = BufReader::new(File::open(sample_path).map_err(|err| {
+        "Unable to open file ".to_owned() + sample_path + ": " + &err.to_string()
+    })?);
     let mut wav_reader = WavReader::new(file_reader).map_err(|err| err.to_string())?;
     let wav_specs = wav_reader.spec();
-    let mut config = RustpotterConfig::default();
-    let sample_rate = wav_specs.sample_rate as usize;
-    config.fmt = wav_specs.try_into()?;
-    config.detector.avg_threshold = command.averaged_threshold;
-    config.detector.threshold = command.threshold;
-    config.detector.min_scores = command.min_scores;
-    config.detector.eager = command.eager;
-    config.detector.score_mode = command.score_mode;
-    config.detector.score_ref = command.score_ref;
-    config.detector.vad_mode = command.vad_mode;
-    config.detector.record_path = command.record_path;
-    config.filters.gain_normalizer.enabled = command.gain_normalizer;
-    config.filters.gain_normalizer.gain_ref = command.gain_ref;
-    config.filters.gain_normalizer.min_gain = command.min_gain;
-    config.filters.gain_normalizer.max_gain = command.max_gain;
-    config.filters.band_pass.enabled = command.band_pass;
-    config.filters.band_pass.low_cutoff = command.low_cutoff;
-    config.filters.band_pass.high_cutoff = command.high_cutoff;
-    if command.debug {
-        println!("Rustpotter config:\n{:?}", config);
-    }
-    let mut rustpotter = Rustpotter::new(&config)?;
-    println!("Loading wakeword file: {}", command.model_path);
-    rustpotter.add_wakeword_from_file("_", &command.model_path)?;
-    let mut partial_detection_counter = 0;
-    let mut chunk_counter = 0;
+    let time_range = get_time_range(&mut wav_reader, options.from_ms, options.to_ms)?;
     match wav_specs.sample_format {
         SampleFormat::Int => match wav_specs.bits_per_sample {
-            8 => run_detection::<i8>(
-                &mut wav_reader,
-                &mut rustpotter,
-                &mut chunk_counter,
-                &mut partial_detection_counter,
-                sample_rate,
-                command.debug,
-                command.debug_gain,
+            8 => run_samples_detection(
+                wav_reader.samples::<i8>(),
+                &wav_specs,
+                time_range,
+                model_paths,
+                settings,
+                options,
             ),
-            16 => run_detection::<i16>(
-                &mut wav_reader,
-                &mut rustpotter,
-                &mut chunk_counter,
-                &mut partial_detection_counter,
-                sample_rate,
-                command.debug,
-                command.debug_gain,
+            16 => run_samples_detection(
+                wav_reader.samples::<i16>(),
+                &wav_specs,
+                time_range,
+                model_paths,
+                settings,
+                options,
             ),
-            32 => run_detection::<i32>(
-                &mut wav_reader,
-                &mut rustpotter,
-                &mut chunk_counter,
-                &mut partial_detection_counter,
-                sample_rate,
-                command.debug,
-                command.debug_gain,
+            32 => run_samples_detection(
+                wav_reader.samples::<i32>(),
+                &wav_specs,
+                time_range,
+                model_paths,
+                settings,
+                options,
             ),
-            _ => panic!("Unsupported wav format"),
+            _ => Err("Unsupported wav format".to_string()),
         },
         SampleFormat::Float => match wav_specs.bits_per_sample {
-            32 => run_detection::<f32>(
-                &mut wav_reader,
-                &mut rustpotter,
-                &mut chunk_counter,
-                &mut partial_detection_counter,
-                sample_rate,
-                command.debug,
-                command.debug_gain,
+            32 => run_samples_detection(
+                wav_reader.samples::<f32>(),
+                &wav_specs,
+                time_range,
+                model_paths,
+                settings,
+                options,
             ),
-            _ => panic!("Unsupported wav format"),
+            _ => Err("Unsupported wav format".to_string()),
         },
+    }
+}
+
+/// Runs the detector over the samples of the time range, which are described by the wav spec.
+pub(crate) fn run_samples_detection<T: Sample, E: ToString>(
+    samples: impl Iterator<Item = Result<T, E>>,
+    wav_specs: &WavSpec,
+    time_range: Range<usize>,
+    model_paths: &[String],
+    settings: &DetectorSettings,
+    options: &RunOptions,
+) -> Result<Vec<FileDetection>, String> {
+    let get_config = || {
+        let mut config = RustpotterConfig {
+            fmt: (*wav_specs).try_into()?,
+            ..Default::default()
+        };
+        settings.apply(&mut config);
+        Ok::<_, String>(config)
     };
-    Ok(())
+    let mut config = get_config()?;
+    config.detector.record_path = options.record_path.clone();
+    if options.debug {
+        println!("Rustpotter config:\n{:?}", config);
+    }
+    let mut rustpotter = Rustpotter::new(&config)?;
+    let wakeword_files = load_wakewords(&mut rustpotter, model_paths, !options.quiet)?;
+    let sample_rate = wav_specs.sample_rate as usize;
+    let mut trace = if options.trace_path.is_some() || options.plot_path.is_some() {
+        let scorers = model_paths
+            .iter()
+            .map(|path| FrameScorer::new(get_config()?, path))
+            .collect::<Result<Vec<_>, String>>()?;
+        Some(DetectionTrace::new(
+            wakeword_files
+                .get_names()
+                .into_iter()
+                .map(|name| {
+                    let threshold = wakeword_files.get_threshold(&name, settings.threshold);
+                    (name, threshold)
+                })
+                .collect(),
+            scorers,
+            options.trace_path.as_deref(),
+            options.plot_path.as_deref(),
+            time_range.start * 1000 / sample_rate,
+            (time_range.len() * 1000 / sample_rate) + options.tail_ms,
+        )?)
+    } else {
+        None
+    };
+    let detections = run_detection(
+        samples,
+        wav_specs,
+        &time_range,
+        &mut rustpotter,
+        &wakeword_files,
+        options,
+        trace.as_mut(),
+    )?;
+    if let Some(trace) = trace {
+        trace.finish()?;
+    }
+    Ok(detections)
 }
 
-fn run_detection<T: Sample + hound::Sample>(
-    wav_reader: &mut WavReader<BufReader<File>>,
+fn run_detection<T: Sample, E: ToString>(
+    samples: impl Iterator<Item = Result<T, E>>,
+    wav_specs: &WavSpec,
+    time_range: &Range<usize>,
     rustpotter: &mut Rustpotter,
-    chunk_counter: &mut usize,
-    partial_detection_counter: &mut usize,
-    sample_rate: usize,
-    debug: bool,
-    debug_gain: bool,
-) {
+    wakeword_files: &WakewordFiles,
+    options: &RunOptions,
+    mut trace: Option<&mut DetectionTrace>,
+) -> Result<Vec<FileDetection>, String> {
     let chunk_size = rustpotter.get_samples_per_frame();
-    let mut buffer = wav_reader
-        .samples::<T>()
-        .map(Result::unwrap)
-        .collect::<Vec<_>>();
-    buffer.append(&mut vec![T::get_zero(); chunk_size * 100]);
-    buffer.chunks_exact(chunk_size).for_each(|chunk| {
-        *chunk_counter += 1;
-        let detection = rustpotter.process_samples(chunk.into());
-        print_detection(
-            rustpotter,
-            detection,
-            partial_detection_counter,
-            debug,
-            debug_gain,
-            || get_time_string(*chunk_counter, chunk_size, sample_rate),
-        );
-    });
+    let sample_rate = wav_specs.sample_rate as usize;
+    let channels = wav_specs.channels as usize;
+    let total_samples = time_range.len() * channels;
+    let tail_samples = sample_rate * options.tail_ms / 1000 * channels;
+    let detection_window_ms = wakeword_files.get_duration_ms();
+    let show_progress = time_range.len() / sample_rate >= PROGRESS_MIN_DURATION_SECS;
+    let mut progress = 0;
+    let mut processed_samples = 0;
+    let mut detections = Vec::new();
+    // file offset, in samples per channel, of the frame that produced the current partial detection
+    let mut partial_detection_end = time_range.start;
+    let mut partial_detection_score = f32::NAN;
+    let mut partial_detection_counter = 0;
+    let mut process_chunk = |chunk: Vec<T>, processed_samples: &mut usize| {
+        *processed_samples += chunk.len();
+        let frame_end = time_range.start + *processed_samples / channels;
+        let scores = trace.as_mut().map(|trace| trace.score_frame(&chunk));
+        let detection = rustpotter.process_samples(chunk);
+        if let (Some(trace), Some(scores)) = (trace.as_mut(), scores) {
+            trace.add_frame(
+                frame_end * 1000 / sample_rate,
+                rustpotter,
+                detection.as_ref(),
+                &scores,
+            )?;
+        }
+        let detection_end = if detection.is_some() {
+            partial_detection_end
+        } else {
+            frame_end
+        };
+        match rustpotter.get_partial_detection() {
+            Some(partial_detection) if partial_detection.score != partial_detection_score => {
+                partial_detection_score = partial_detection.score;
+                partial_detection_end = frame_end;
+            }
+            Some(_) => {}
+            None => partial_detection_score = f32::NAN,
+        }
+        if let Some(detection) = detection.as_ref() {
+            let end_ms = detection_end * 1000 / sample_rate;
+            detections.push(FileDetection {
+                name: detection.name.clone(),
+                model_path: wakeword_files.get_path(&detection.name).to_string(),
+                start_ms: end_ms.saturating_sub(detection_window_ms),
+                end_ms,
+                score: detection.score,
+            });
+        }
+        if !options.quiet {
+            print_detection(
+                rustpotter,
+                wakeword_files,
+                detection,
+                &mut partial_detection_counter,
+                options.debug,
+                options.debug_gain,
+                || get_time_range_string(detection_end, detection_window_ms, sample_rate),
+            );
+        }
+        Ok::<(), String>(())
+    };
+    let samples = samples
+        .take(total_samples)
+        .map(|sample| sample.map_err(|err| err.to_string()))
+        .chain(std::iter::repeat_with(|| Ok(T::get_zero())).take(tail_samples));
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut next_block_size = || {
+        options
+            .buffer_simulation
+            .as_ref()
+            .map_or(chunk_size, |buffer_simulation| {
+                buffer_simulation.next_size(&mut rng) * channels
+            })
+    };
+    let started = Instant::now();
+    let mut fed_samples = 0;
+    let mut block_size = next_block_size();
+    let mut block: Vec<T> = Vec::with_capacity(block_size);
+    let mut buffer: Vec<T> = Vec::new();
+    for sample in samples {
+        block.push(sample?);
+        if block.len() == block_size {
+            for frame in drain_frames(&mut buffer, &block, chunk_size) {
+                process_chunk(frame, &mut processed_samples)?;
+            }
+            fed_samples += block.len();
+            block.clear();
+            block_size = next_block_size();
+            if options.realtime {
+                let fed_time =
+                    Duration::from_secs_f64((fed_samples / channels) as f64 / sample_rate as f64);
+                if let Some(wait_time) = fed_time.checked_sub(started.elapsed()) {
+                    std::thread::sleep(wait_time);
+                }
+            }
+            if show_progress {
+                print_progress(fed_samples, total_samples, &mut progress);
+            }
+        }
+    }
+    drain_frames(&mut buffer, &block, chunk_size)
+        .try_for_each(|frame| process_chunk(frame, &mut processed_samples))?;
+    if !buffer.is_empty() {
+        // pad the last partial chunk instead of dropping it
+        buffer.resize(chunk_size, T::get_zero());
+        process_chunk(buffer, &mut processed_samples)?;
+    }
+    if show_progress {
+        eprintln!();
+    }
+    Ok(detections)
+}
+fn print_progress(processed_samples: usize, total_samples: usize, progress: &mut usize) {
+    let percent = (processed_samples.min(total_samples) * 100) / total_samples.max(1);
+    if percent > *progress {
+        *progress = percent;
+        eprint!("\rProcessed {}%", percent);
+    }
+}
+/// Seeks the reader to the range start and returns the range to evaluate, in samples per channel.
+fn get_time_range(
+    wav_reader: &mut WavReader<BufReader<File>>,
+    from_ms: Option<usize>,
+    to_ms: Option<usize>,
+) -> Result<Range<usize>, String> {
+    let time_range = get_sample_range(
+        wav_reader.spec().sample_rate as usize,
+        wav_reader.duration() as usize,
+        from_ms,
+        to_ms,
+    )?;
+    if time_range.start != 0 {
+        wav_reader
+            .seek(time_range.start as u32)
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(time_range)
+}
+/// Returns the range of samples per channel selected by the offsets.
+fn get_sample_range(
+    sample_rate: usize,
+    duration: usize,
+    from_ms: Option<usize>,
+    to_ms: Option<usize>,
+) -> Result<Range<usize>, String> {
+    let start = from_ms
+        .map_or(0, |ms| ms * sample_rate / 1000)
+        .min(duration);
+    let end = to_ms
+        .map_or(duration, |ms| ms * sample_rate / 1000)
+        .min(duration);
+    if start >= end {
+        return Err("Empty time range selected".to_string());
+    }
+    Ok(start..end)
+}
+/// Parses a time offset in the format "[[hh:]mm:]ss[.mmm]" into milliseconds.
+pub(crate) fn parse_time_offset(value: &str) -> Result<usize, String> {
+    let invalid_value = || format!("Invalid time offset '{}'", value);
+    let mut parts = value.rsplitn(3, ':');
+    let seconds: f64 = parts
+        .next()
+        .and_then(|seconds| seconds.parse().ok())
+        .filter(|seconds: &f64| *seconds >= 0.)
+        .ok_or_else(invalid_value)?;
+    let mut total_ms = (seconds * 1000.).round() as usize;
+    for unit_ms in [60_000, 3_600_000] {
+        if let Some(part) = parts.next() {
+            total_ms += part.parse::<usize>().map_err(|_| invalid_value())? * unit_ms;
+        }
+    }
+    Ok(total_ms)
 }
-fn get_time_string(chunk_number: usize, chunk_size: usize, sample_rate: usize) -> String {
-    let total_seconds = (chunk_number * chunk_size) as f32 / sample_rate as f32;
-    let minutes = (total_seconds / 60.).floor() as i32;
-    let seconds = (total_seconds % 60.).floor() as i32;
-    format!("00:{:02}:{:02}", minutes, seconds)
+fn get_time_range_string(end: usize, window_ms: usize, sample_rate: usize) -> String {
+    let end_ms = end * 1000 / sample_rate;
+    format!(
+        "{} - {}",
+        get_time_string(end_ms.saturating_sub(window_ms)),
+        get_time_string(end_ms)
+    )
+}
+pub(crate) fn get_time_string(total_ms: usize) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        total_ms / 3_600_000,
+        (total_ms / 60_000) % 60,
+        (total_ms / 1000) % 60,
+        total_ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_time_offset_accepts_each_format() {
+        assert_eq!(parse_time_offset("12.25"), Ok(12_250));
+        assert_eq!(parse_time_offset("90"), Ok(90_000));
+        assert_eq!(parse_time_offset("01:30"), Ok(90_000));
+        assert_eq!(parse_time_offset("1:02:03.5"), Ok(3_723_500));
+        assert_eq!(parse_time_offset("0"), Ok(0));
+    }
+
+    #[test]
+    fn parse_time_offset_rejects_malformed_values() {
+        for value in ["", "-1", "a:10", "1:-2", "1:2:3:4", "10s", ":"] {
+            assert!(parse_time_offset(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn time_string_formats_the_offset() {
+        assert_eq!(get_time_string(3_723_500), "01:02:03.500");
+        assert_eq!(get_time_string(0), "00:00:00.000");
+    }
 }
@@ -0,0 +1,365 @@
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use rustpotter::{
+    Endianness, Rustpotter, RustpotterConfig, RustpotterDetection, Sample, SampleFormat,
+    MFCCS_EXTRACTOR_FRAME_LENGTH_MS,
+};
+
+use super::spot::{add_wakeword_without_thresholds, read_wakeword_info, MFCC_FRAME_SHIFT_MS};
+use super::test::get_time_string;
+
+/// Number of columns used to draw the plot timeline.
+const PLOT_WIDTH: usize = 1600;
+const PLOT_MARGIN: usize = 60;
+const PLOT_ENVELOPE_HEIGHT: usize = 120;
+const PLOT_SCORES_HEIGHT: usize = 240;
+const PLOT_COLORS: [&str; 6] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#9467bd", "#8c564b", "#e377c2",
+];
+
+/// Collects per frame detector information, writing it to a csv file and/or a svg plot.
+pub(crate) struct DetectionTrace {
+    wakewords: Vec<String>,
+    /// Scorer of each wakeword file, so the score of a wakeword is traced
+    /// even when a wakeword of another file scores higher on the same frame.
+    scorers: Vec<FrameScorer>,
+    csv_writer: Option<BufWriter<File>>,
+    plot: Option<(String, ScorePlot)>,
+}
+impl DetectionTrace {
+    /// Expects the wakeword names along with the threshold the detector applies to each one.
+    pub(crate) fn new(
+        wakewords: Vec<(String, f32)>,
+        scorers: Vec<FrameScorer>,
+        csv_path: Option<&str>,
+        plot_path: Option<&str>,
+        start_ms: usize,
+        duration_ms: usize,
+    ) -> Result<DetectionTrace, String> {
+        let (wakewords, thresholds): (Vec<_>, Vec<_>) = wakewords.into_iter().unzip();
+        let csv_writer = csv_path
+            .map(|path| {
+                let mut writer = BufWriter::new(File::create(path).map_err(|err| err.to_string())?);
+                write!(writer, "time_ms,rms,gain,partial_detections")
+                    .and_then(|_| {
+                        wakewords.iter().try_for_each(|name| {
+                            write!(writer, ",\"{}\"", name.replace('"', "\"\""))
+                        })
+                    })
+                    .and_then(|_| writeln!(writer))
+                    .map_err(|err| err.to_string())?;
+                Ok::<_, String>(writer)
+            })
+            .transpose()?;
+        let plot = plot_path.map(|path| {
+            (
+                path.to_string(),
+                ScorePlot::new(thresholds, start_ms, duration_ms),
+            )
+        });
+        Ok(DetectionTrace {
+            wakewords,
+            scorers,
+            csv_writer,
+            plot,
+        })
+    }
+    /// Scores the frame samples against each wakeword, in the same order as the trace columns.
+    pub(crate) fn score_frame<T: Sample>(&mut self, samples: &[T]) -> Vec<Option<f32>> {
+        let frame = samples
+            .iter()
+            .flat_map(|sample| sample.into_f32().to_le_bytes())
+            .collect::<Vec<_>>();
+        let scored_wakewords = self
+            .scorers
+            .iter_mut()
+            .map(|scorer| scorer.score_frame(&frame))
+            .collect::<Vec<_>>();
+        self.wakewords
+            .iter()
+            .map(|name| {
+                scored_wakewords
+                    .iter()
+                    .flatten()
+                    .find(|(scored_name, _)| scored_name.eq(name))
+                    .map(|(_, score)| *score)
+            })
+            .collect()
+    }
+    /// Adds the detector state after processing the frame that ends at the provided offset,
+    /// along with the frame scores.
+    pub(crate) fn add_frame(
+        &mut self,
+        end_ms: usize,
+        rustpotter: &Rustpotter,
+        detection: Option<&RustpotterDetection>,
+        scores: &[Option<f32>],
+    ) -> Result<(), String> {
+        let partial_detection = rustpotter.get_partial_detection();
+        if let Some(writer) = self.csv_writer.as_mut() {
+            write!(
+                writer,
+                "{},{},{},{}",
+                end_ms,
+                rustpotter.get_rms_level(),
+                rustpotter.get_gain(),
+                partial_detection.map_or(0, |partial_detection| partial_detection.counter)
+            )
+            .and_then(|_| {
+                scores.iter().try_for_each(|score| match score {
+                    Some(score) => write!(writer, ",{}", score),
+                    None => write!(writer, ","),
+                })
+            })
+            .and_then(|_| writeln!(writer))
+            .map_err(|err| err.to_string())?;
+        }
+        if let Some((_, plot)) = self.plot.as_mut() {
+            let detection_index = detection.and_then(|detection| {
+                self.wakewords
+                    .iter()
+                    .position(|name| detection.name.eq(name))
+            });
+            plot.add_frame(end_ms, rustpotter.get_rms_level(), scores, detection_index);
+        }
+        Ok(())
+    }
+    /// Flushes the csv file and renders the plot.
+    pub(crate) fn finish(self) -> Result<(), String> {
+        if let Some(mut writer) = self.csv_writer {
+            writer.flush().map_err(|err| err.to_string())?;
+        }
+        if let Some((path, plot)) = self.plot {
+            std::fs::write(&path, plot.render(&self.wakewords)).map_err(|err| err.to_string())?;
+            println!("Plot written to {}", path);
+        }
+        Ok(())
+    }
+}
+
+/// Scores each frame against the wakewords of a file.
+/// The detector keeps the best score of a partial detection until it is emitted, and only a reset discards it,
+/// so the file is loaded on a rotation of detectors with threshold 0, each one read and reset on the frame
+/// that completes its mfcc window, to get the score of every frame instead of the best one so far.
+pub(crate) struct FrameScorer {
+    detectors: Vec<Rustpotter>,
+    frame_index: usize,
+}
+impl FrameScorer {
+    /// Expects the detector config of the traced run, the frames are provided as little endian f32 samples.
+    pub(crate) fn new(mut config: RustpotterConfig, path: &str) -> Result<FrameScorer, String> {
+        config.fmt.sample_format = SampleFormat::F32;
+        config.fmt.endianness = Endianness::Little;
+        config.detector.threshold = 0.;
+        config.detector.avg_threshold = 0.;
+        config.detector.eager = false;
+        config.detector.vad_mode = None;
+        config.detector.record_path = None;
+        let buffer = std::fs::read(path)
+            .map_err(|err| "Unable to open file ".to_owned() + path + ": " + &err.to_string())?;
+        let mfcc_frames_per_frame = MFCCS_EXTRACTOR_FRAME_LENGTH_MS / MFCC_FRAME_SHIFT_MS;
+        // the first frame after a reset only fills the mfcc extractor buffer
+        let window_frames = read_wakeword_info(path)?
+            .mfcc_frames
+            .div_ceil(mfcc_frames_per_frame)
+            + 1;
+        let detectors = (0..window_frames)
+            .map(|_| {
+                let mut detector = Rustpotter::new(&config)?;
+                add_wakeword_without_thresholds(&mut detector, path, &buffer)?;
+                Ok(detector)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(FrameScorer {
+            detectors,
+            frame_index: 0,
+        })
+    }
+    /// Returns the name and score of the best wakeword on the frame, once enough audio was processed to score it.
+    fn score_frame(&mut self, frame: &[u8]) -> Option<(String, f32)> {
+        for detector in self.detectors.iter_mut() {
+            detector.process_bytes(frame);
+        }
+        let window_frames = self.detectors.len();
+        let detector = &mut self.detectors[self.frame_index % window_frames];
+        self.frame_index += 1;
+        let scored_wakeword = detector
+            .get_partial_detection()
+            .map(|partial_detection| (partial_detection.name.clone(), partial_detection.score));
+        detector.reset();
+        scored_wakeword
+    }
+}
+/// Escapes the text to be placed inside a svg element or attribute.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Timeline of the frame rms levels and wakeword scores, aggregated by plot column.
+struct ScorePlot {
+    start_ms: usize,
+    duration_ms: usize,
+    /// Threshold of each wakeword.
+    thresholds: Vec<f32>,
+    rms_levels: Vec<f32>,
+    scores: Vec<Vec<f32>>,
+    detections: Vec<(usize, usize)>,
+}
+impl ScorePlot {
+    fn new(thresholds: Vec<f32>, start_ms: usize, duration_ms: usize) -> ScorePlot {
+        ScorePlot {
+            start_ms,
+            duration_ms: duration_ms.max(1),
+            scores: vec![vec![0.; PLOT_WIDTH]; thresholds.len()],
+            thresholds,
+            rms_levels: vec![0.; PLOT_WIDTH],
+            detections: Vec::new(),
+        }
+    }
+    fn get_column(&self, time_ms: usize) -> usize {
+        ((time_ms.saturating_sub(self.start_ms) * PLOT_WIDTH) / self.duration_ms)
+            .min(PLOT_WIDTH - 1)
+    }
+    fn add_frame(
+        &mut self,
+        end_ms: usize,
+        rms_level: f32,
+        scores: &[Option<f32>],
+        detection_index: Option<usize>,
+    ) {
+        let column = self.get_column(end_ms);
+        self.rms_levels[column] = self.rms_levels[column].max(rms_level);
+        for (index, score) in scores.iter().enumerate() {
+            if let Some(score) = score {
+                self.scores[index][column] = self.scores[index][column].max(*score);
+            }
+        }
+        if let Some(detection_index) = detection_index {
+            self.detections.push((end_ms, detection_index));
+        }
+    }
+    fn render(&self, wakewords: &[String]) -> String {
+        let width = PLOT_WIDTH + PLOT_MARGIN * 2;
+        let envelope_top = PLOT_MARGIN;
+        let scores_top = envelope_top + PLOT_ENVELOPE_HEIGHT + PLOT_MARGIN / 2;
+        let scores_bottom = scores_top + PLOT_SCORES_HEIGHT;
+        let height = scores_bottom + PLOT_MARGIN;
+        let mut svg = String::new();
+        _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" font-family="monospace" font-size="11">"#,
+            width, height
+        );
+        _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+        // waveform envelope, mirrored around its center line
+        let max_rms_level = self.rms_levels.iter().copied().fold(f32::EPSILON, f32::max);
+        let envelope_center = envelope_top as f32 + PLOT_ENVELOPE_HEIGHT as f32 / 2.;
+        let mut envelope = String::new();
+        for (column, rms_level) in self.rms_levels.iter().enumerate() {
+            let half_height = rms_level / max_rms_level * PLOT_ENVELOPE_HEIGHT as f32 / 2.;
+            _ = write!(
+                envelope,
+                "M{} {:.1}V{:.1}",
+                PLOT_MARGIN + column,
+                envelope_center - half_height,
+                envelope_center + half_height
+            );
+        }
+        _ = writeln!(
+            svg,
+            r##"<path d="{}" stroke="#888888" stroke-width="1"/>"##,
+            envelope
+        );
+        _ = writeln!(
+            svg,
+            r#"<text x="{}" y="{}">rms (max {:.5})</text>"#,
+            PLOT_MARGIN,
+            envelope_top - 6,
+            max_rms_level
+        );
+        // score axis
+        _ = writeln!(
+            svg,
+            r##"<rect x="{}" y="{}" width="{}" height="{}" fill="none" stroke="#cccccc"/>"##,
+            PLOT_MARGIN, scores_top, PLOT_WIDTH, PLOT_SCORES_HEIGHT
+        );
+        let score_y = |score: f32| scores_bottom as f32 - score * PLOT_SCORES_HEIGHT as f32;
+        // score curves
+        for (index, scores) in self.scores.iter().enumerate() {
+            let color = PLOT_COLORS[index % PLOT_COLORS.len()];
+            let threshold = self.thresholds[index];
+            _ = writeln!(
+                svg,
+                r#"<line x1="{}" x2="{}" y1="{:.1}" y2="{:.1}" stroke="{}" stroke-dasharray="6 4"/>"#,
+                PLOT_MARGIN,
+                PLOT_MARGIN + PLOT_WIDTH,
+                score_y(threshold),
+                score_y(threshold),
+                color
+            );
+            // wakewords sharing the threshold share its label
+            if !self.thresholds[..index].contains(&threshold) {
+                _ = writeln!(
+                    svg,
+                    r##"<text x="{}" y="{:.1}" text-anchor="end" fill="#555555">{}</text>"##,
+                    PLOT_MARGIN - 4,
+                    score_y(threshold) + 4.,
+                    threshold
+                );
+            }
+            let points = scores
+                .iter()
+                .enumerate()
+                .map(|(column, score)| format!("{},{:.1}", PLOT_MARGIN + column, score_y(*score)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            _ = writeln!(
+                svg,
+                r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="1.5"/>"#,
+                points, color
+            );
+            _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" fill="{}">{}</text>"#,
+                PLOT_MARGIN + index * 160,
+                height - 12,
+                color,
+                escape_xml(&wakewords[index])
+            );
+        }
+        // detection markers
+        for (end_ms, index) in &self.detections {
+            let x = PLOT_MARGIN + self.get_column(*end_ms);
+            _ = writeln!(
+                svg,
+                r#"<line x1="{}" x2="{}" y1="{}" y2="{}" stroke="{}" stroke-width="2"/>"#,
+                x,
+                x,
+                envelope_top,
+                scores_bottom,
+                PLOT_COLORS[index % PLOT_COLORS.len()]
+            );
+        }
+        // time axis
+        for tick in 0..=10 {
+            let x = PLOT_MARGIN + tick * PLOT_WIDTH / 10;
+            _ = writeln!(
+                svg,
+                r#"<text x="{}" y="{}" text-anchor="middle">{}</text>"#,
+                x,
+                scores_bottom + 14,
+                get_time_string(self.start_ms + tick * self.duration_ms / 10)
+            );
+        }
+        _ = writeln!(svg, "</svg>");
+        svg
+    }
+}
@@ -1,9 +1,37 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    path::Path,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
 use clap::Args;
+use gag::BufferRedirect;
+use rand::Rng;
 use rustpotter::{
     ModelType, WakewordLoad, WakewordModel, WakewordModelTrain, WakewordModelTrainOptions,
     WakewordSave,
 };
 
+use super::{
+    audio::get_wav_paths,
+    augment::{AugmentSettings, Augmenter},
+    filter::get_sample_rms_level,
+    metadata::WakewordMetadata,
+    metrics::{MetricsLog, TestRecords},
+    provenance::ProvenanceFilter,
+    split::{get_label, split_data_dir, SplitSettings, NONE_LABEL},
+    weights::init_model,
+};
+
+/// Start of the line printed by the library when training from a model.
+const FROM_MODEL_NOTICE: &str = "Training from previous model";
+/// Min duration of the rounds, as times the records features extraction, so extracting them again on each round adds little time.
+const ROUND_EXTRACTION_RATIO: u32 = 20;
+/// Min duration of the rounds.
+const MIN_ROUND_DURATION: Duration = Duration::from_secs(1);
+
 #[derive(Args, Debug)]
 /// Train wakeword model, using wav audio files
 #[clap()]
@@ -14,12 +42,14 @@ pub struct TrainCommand {
     #[clap(short = 't', long, default_value_t = ModelType::Medium)]
     /// Generated model type
     model_type: ModelType,
-    #[clap(long, required = true)]
+    #[clap(long, required_unless_present = "data_dir")]
     /// Train data directory path
-    train_dir: String,
-    #[clap(long, required = true)]
+    train_dir: Option<String>,
+    #[clap(long, required_unless_present = "data_dir")]
     /// Test data directory path
-    test_dir: String,
+    test_dir: Option<String>,
+    #[clap(flatten)]
+    split: SplitSettings,
     #[clap(short = 'l', long, default_value_t = 0.03)]
     /// Training learning rate
     learning_rate: f64,
@@ -35,25 +65,410 @@ pub struct TrainCommand {
     #[clap(short = 'm', long)]
     /// Model to continue training from
     from_model: Option<String>,
+    #[clap(flatten)]
+    provenance_filter: ProvenanceFilter,
+    #[clap(flatten)]
+    augment: AugmentSettings,
+    #[clap(long)]
+    /// Seed used to initialize the model weights, split the data directory and augment the training records.
+    /// A random one is used if unset, it is printed and stored in the model metadata.
+    seed: Option<u64>,
+    #[clap(long)]
+    /// Stop the training when neither the test accuracy has improved nor the train loss has decreased
+    /// by min-loss-delta for this number of epochs.
+    /// The model is tested every test-epochs to detect it, the records features are extracted again each time,
+    /// so a low test-epochs slows down the training.
+    patience: Option<usize>,
+    #[clap(long, default_value_t = 0.001, requires = "patience")]
+    /// Min train loss decrease considered an improvement by the patience.
+    min_loss_delta: f32,
+    #[clap(long)]
+    /// Save the model of the epoch with the best test accuracy, using the lower train loss to break ties,
+    /// instead of the one of the last epoch.
+    /// The records features are extracted again every test-epochs, so a low value slows down the training.
+    save_best: bool,
+    #[clap(long)]
+    /// Write a copy of the model every this number of epochs, next to the model path with the epoch as suffix.
+    /// The records features are extracted again on each one.
+    checkpoint_every: Option<usize>,
+    #[clap(long)]
+    /// Write the test metrics of each test epoch to this path, as json lines.
+    /// They include the train loss, the test accuracy and the precision and recall of each label.
+    /// The records features are extracted again every test-epochs, so a low value slows down the training.
+    metrics: Option<String>,
+}
+/// Model with the best test result, serialized.
+struct BestModel {
+    epoch: usize,
+    loss: f32,
+    accuracy: f32,
+    buffer: Vec<u8>,
+}
+impl BestModel {
+    fn is_improved_by(&self, loss: f32, accuracy: f32) -> bool {
+        accuracy > self.accuracy || (accuracy == self.accuracy && loss < self.loss)
+    }
+}
+/// Models produced by the training rounds.
+struct TrainedModels {
+    last: WakewordModel,
+    best: Option<BestModel>,
+    interrupted: bool,
+    /// Test records features, computed once for all the rounds.
+    test_records: TestRecords,
 }
 pub fn train(command: TrainCommand) -> Result<(), String> {
     println!("Start training {}!", command.model_path);
-    let model: Option<WakewordModel> = if let Some(wakeword_model_path) = command.from_model {
-        Some(WakewordModel::load_from_file(&wakeword_model_path)?)
+    if command.patience == Some(0) || command.checkpoint_every == Some(0) {
+        return Err("The patience and checkpoint epochs should be over zero".to_string());
+    }
+    let seed = command.seed.unwrap_or_else(|| rand::thread_rng().gen());
+    println!("Using seed {}", seed);
+    let (train_samples, test_samples) = match command.split.data_dir.as_ref() {
+        Some(data_dir) => {
+            println!("Splitting {}", data_dir);
+            let split = split_data_dir(data_dir, &command.split, seed, &command.provenance_filter)?;
+            let manifest_path = Path::new(&command.model_path).with_extension("split.json");
+            split.write_manifest(&manifest_path, data_dir, &command.split, seed)?;
+            println!("Split written to {}", manifest_path.display());
+            (read_samples(&split.train)?, read_samples(&split.test)?)
+        }
+        None => {
+            let train_dir = command.train_dir.as_ref().expect("Missing train dir");
+            let test_dir = command.test_dir.as_ref().expect("Missing test dir");
+            (
+                read_samples(
+                    &command
+                        .provenance_filter
+                        .filter_paths(get_wav_paths(train_dir)?)?,
+                )?,
+                read_samples(
+                    &command
+                        .provenance_filter
+                        .filter_paths(get_wav_paths(test_dir)?)?,
+                )?,
+            )
+        }
+    };
+    // the library initializes the weights randomly, so the training starts from a model initialized with the seed
+    let model = match command.from_model.as_ref() {
+        Some(wakeword_model_path) => WakewordModel::load_from_file(wakeword_model_path)?,
+        None => init_model(
+            &command.model_type,
+            command.mfcc_size,
+            &train_samples,
+            &test_samples,
+            seed,
+        )?,
+    };
+    let augmenter = if command.augment.augment {
+        println!("Augmenting the training records.");
+        Some(Augmenter::new(&command.augment, seed)?)
     } else {
         None
     };
-    let options = WakewordModelTrainOptions::new(
-        command.model_type,
-        command.learning_rate,
-        command.epochs,
-        command.test_epochs,
-        command.mfcc_size,
-    );
-    let wakeword =
-        WakewordModel::train_from_dirs(options, command.train_dir, command.test_dir, model)
-            .map_err(|err| err.to_string())?;
+    let rms_level = get_rms_level(&train_samples)?;
+    let trained_models = train_rounds(
+        &command,
+        train_samples,
+        test_samples,
+        model,
+        augmenter,
+        rms_level,
+    )?;
+    let mut wakeword = match trained_models.best {
+        Some(best) if command.save_best || trained_models.interrupted => {
+            println!(
+                "Using the model of epoch {}, test acc: {:5.2}%",
+                best.epoch, best.accuracy
+            );
+            WakewordModel::load_from_buffer(&best.buffer)?
+        }
+        _ => trained_models.last,
+    };
+    wakeword.rms_level = rms_level;
     wakeword.save_to_file(&command.model_path)?;
+    let mut metadata = WakewordMetadata::new();
+    metadata.seed = Some(seed);
+    metadata.write(&command.model_path)?;
     println!("{} created!", command.model_path);
+    trained_models.test_records.evaluate(&wakeword)?.print();
+    Ok(())
+}
+/// Trains in rounds continuing from the model of the previous one, printing the progress of each one when it ends.
+/// Rounds last the test epochs needed to reach the min round duration, and end earlier at the checkpoints,
+/// at the augmentation epochs, and at each test epoch when the best model is tracked or the metrics are written.
+fn train_rounds(
+    command: &TrainCommand,
+    train_samples: HashMap<String, Vec<u8>>,
+    test_samples: HashMap<String, Vec<u8>>,
+    model: WakewordModel,
+    mut augmenter: Option<Augmenter>,
+    rms_level: f32,
+) -> Result<TrainedModels, String> {
+    let (tx, rx) = mpsc::channel();
+    let mut interruptions = 0;
+    ctrlc::set_handler(move || {
+        interruptions += 1;
+        if interruptions > 1 {
+            eprintln!("Training aborted.");
+            std::process::exit(1);
+        }
+        // printed to stderr as the stdout is captured during the rounds
+        eprintln!("Stopping the training at the end of the current round, press Ctrl-C again to abort it.");
+        tx.send(()).expect("Could not send signal on channel.")
+    })
+    .expect("Unable to listen keyboard");
+    let test_epochs = command.test_epochs.max(1);
+    let extraction_started = Instant::now();
+    let test_records = TestRecords::new(&test_samples, model.mfcc_size)?;
+    let record_extraction_time = extraction_started.elapsed() / test_samples.len().max(1) as u32;
+    // the first round measures the epoch duration to size the next ones
+    let mut round_duration_epochs = test_epochs;
+    let mut short_rounds_warned = false;
+    let mut metrics_log = command
+        .metrics
+        .as_deref()
+        .map(MetricsLog::new)
+        .transpose()?;
+    let test_rounds = command.save_best || command.patience.is_some() || metrics_log.is_some();
+    let mut round_limits = vec![command.checkpoint_every];
+    if augmenter.is_some() {
+        round_limits.push(Some(command.augment.augment_epochs.max(1)));
+    }
+    if test_rounds {
+        round_limits.push(Some(test_epochs));
+    }
+    let mut model = Some(model);
+    let mut best: Option<BestModel> = None;
+    // epoch, loss and accuracy of the last improvement counted by the patience
+    let mut last_improvement = (0, f32::INFINITY, f32::NEG_INFINITY);
+    let mut round_samples = HashMap::new();
+    let mut epoch = 0;
+    while epoch < command.epochs {
+        let round_end = round_limits
+            .iter()
+            .flatten()
+            .map(|epochs| (epoch / epochs + 1) * epochs)
+            .fold(
+                command.epochs.min(epoch + round_duration_epochs),
+                usize::min,
+            );
+        let round_epochs = round_end - epoch;
+        let options = WakewordModelTrainOptions::new(
+            command.model_type.clone(),
+            command.learning_rate,
+            round_epochs,
+            test_epochs,
+            command.mfcc_size,
+        );
+        if epoch % command.augment.augment_epochs.max(1) == 0 || round_samples.is_empty() {
+            round_samples = match augmenter.as_mut() {
+                Some(augmenter) => augmenter.augment(&train_samples)?,
+                None => train_samples.clone(),
+            };
+        }
+        let round_started = Instant::now();
+        let extraction_time =
+            record_extraction_time * (round_samples.len() + test_samples.len()) as u32;
+        std::io::stdout().flush().map_err(|err| err.to_string())?;
+        let mut output_redirect = BufferRedirect::stdout().map_err(|err| err.to_string())?;
+        let result = WakewordModel::train_from_buffers(
+            options,
+            round_samples.clone(),
+            test_samples.clone(),
+            model.take(),
+        );
+        std::io::stdout().flush().map_err(|err| err.to_string())?;
+        let mut round_output = String::new();
+        output_redirect
+            .read_to_string(&mut round_output)
+            .map_err(|err| err.to_string())?;
+        drop(output_redirect);
+        let round_result = print_round_progress(&round_output, epoch, command);
+        let trained_model = result.map_err(|err| err.to_string())?;
+        // includes the features extraction, so short rounds underestimate the epochs of the next one
+        let epoch_time = round_started.elapsed() / round_epochs as u32;
+        let round_duration = (extraction_time * ROUND_EXTRACTION_RATIO).max(MIN_ROUND_DURATION);
+        let duration_epochs =
+            (round_duration.as_secs_f64() / epoch_time.as_secs_f64().max(f64::EPSILON)) as usize;
+        round_duration_epochs = test_epochs * duration_epochs.div_ceil(test_epochs).max(1);
+        if let Some(min_limit) = round_limits.iter().flatten().min() {
+            if !short_rounds_warned && *min_limit < round_duration_epochs {
+                println!(
+                    "Warning: the records features are extracted again every {} epochs, which slows down the training.",
+                    min_limit
+                );
+                short_rounds_warned = true;
+            }
+        }
+        epoch = round_end;
+        // the library always tests the model on the last epoch of the round
+        if let Some((loss, accuracy)) = round_result {
+            if let Some(metrics_log) = metrics_log.as_mut() {
+                if epoch % test_epochs == 0 || epoch == command.epochs {
+                    let test_result = test_records.evaluate(&trained_model)?;
+                    metrics_log.write(epoch, loss, command.learning_rate, &test_result)?;
+                }
+            }
+            let (_, improvement_loss, improvement_accuracy) = last_improvement;
+            if accuracy > improvement_accuracy || loss < improvement_loss - command.min_loss_delta {
+                last_improvement = (
+                    epoch,
+                    loss.min(improvement_loss),
+                    accuracy.max(improvement_accuracy),
+                );
+            }
+            if best
+                .as_ref()
+                .is_none_or(|best| best.is_improved_by(loss, accuracy))
+            {
+                best = Some(BestModel {
+                    epoch,
+                    loss,
+                    accuracy,
+                    buffer: trained_model.save_to_buffer()?,
+                });
+            }
+        }
+        if command
+            .checkpoint_every
+            .is_some_and(|epochs| epoch % epochs == 0 && epoch < command.epochs)
+        {
+            save_checkpoint(&trained_model, &command.model_path, epoch, rms_level)?;
+        }
+        model = Some(trained_model);
+        if let Some(patience) = command.patience {
+            if epoch - last_improvement.0 >= patience && epoch < command.epochs {
+                println!(
+                    "Stopping at epoch {}, no improvement in {} epochs.",
+                    epoch, patience
+                );
+                break;
+            }
+        }
+        if rx.try_recv().is_ok() && epoch < command.epochs {
+            println!("Training stopped at epoch {}.", epoch);
+            return Ok(TrainedModels {
+                last: model.expect("Missing trained model"),
+                best,
+                interrupted: true,
+                test_records,
+            });
+        }
+    }
+    Ok(TrainedModels {
+        last: model.expect("Missing trained model"),
+        best,
+        interrupted: false,
+        test_records,
+    })
+}
+/// Writes a copy of the model, with the epoch appended to its file name.
+fn save_checkpoint(
+    model: &WakewordModel,
+    model_path: &str,
+    epoch: usize,
+    rms_level: f32,
+) -> Result<(), String> {
+    let path = Path::new(model_path);
+    let checkpoint_path = path.with_file_name(format!(
+        "{}-epoch{}.{}",
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default(),
+        epoch,
+        path.extension()
+            .map(|extension| extension.to_string_lossy())
+            .unwrap_or("rpw".into())
+    ));
+    let mut checkpoint = WakewordModel::load_from_buffer(&model.save_to_buffer()?)?;
+    checkpoint.rms_level = rms_level;
+    checkpoint.save_to_file(&checkpoint_path.to_string_lossy())?;
+    println!("Checkpoint {} saved.", checkpoint_path.display());
     Ok(())
 }
+/// Prints the output of a round, the training summary is only printed on the first one,
+/// and the progress lines are numbered from the first round.
+/// Returns the train loss and test accuracy of the last epoch of the round.
+fn print_round_progress(
+    round_output: &str,
+    epoch: usize,
+    command: &TrainCommand,
+) -> Option<(f32, f32)> {
+    let from_model = command.from_model.is_some();
+    let mut round_result = None;
+    for line in round_output.lines() {
+        let progress = line
+            .trim_start()
+            .split_once(' ')
+            .filter(|(_, progress)| progress.starts_with("train loss"))
+            .and_then(|(round_epoch, progress)| {
+                round_epoch
+                    .parse::<usize>()
+                    .ok()
+                    .map(|round_epoch| (round_epoch, progress))
+            });
+        match progress {
+            // the test accuracy of the model the training starts from
+            Some((0, _)) if epoch == 0 && from_model => println!("{}", line),
+            Some((0, _)) => {}
+            Some((round_epoch, progress)) => {
+                let total_epoch = epoch + round_epoch;
+                if total_epoch % command.test_epochs.max(1) == 0 || total_epoch == command.epochs {
+                    println!("{:4} {}", total_epoch, progress);
+                }
+                round_result = parse_progress(progress);
+            }
+            None if epoch == 0 && (from_model || !line.starts_with(FROM_MODEL_NOTICE)) => {
+                println!("{}", line)
+            }
+            None => {}
+        }
+    }
+    round_result
+}
+/// Parses the train loss and test accuracy of a progress line.
+fn parse_progress(progress: &str) -> Option<(f32, f32)> {
+    let (loss, accuracy) = progress
+        .strip_prefix("train loss:")?
+        .split_once("test acc:")?;
+    Some((
+        loss.trim().parse().ok()?,
+        accuracy.trim().trim_end_matches('%').parse().ok()?,
+    ))
+}
+/// Returns the wakeword records rms level, averaged like the library does but in name order,
+/// so it does not change between runs.
+fn get_rms_level(train_samples: &HashMap<String, Vec<u8>>) -> Result<f32, String> {
+    let mut names = train_samples
+        .keys()
+        .filter(|name| get_label(name) != NONE_LABEL)
+        .collect::<Vec<_>>();
+    names.sort();
+    let mut rms_level = f32::NAN;
+    for name in names {
+        let sample_rms_level = get_sample_rms_level(&train_samples[name])?;
+        rms_level = if rms_level.is_nan() {
+            sample_rms_level
+        } else {
+            (rms_level + sample_rms_level) / 2.
+        };
+    }
+    Ok(rms_level)
+}
+/// Reads the wav records, keyed by file name.
+fn read_samples(paths: &[String]) -> Result<HashMap<String, Vec<u8>>, String> {
+    let mut samples = HashMap::new();
+    for path in paths {
+        let file_name = Path::new(path)
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        samples.insert(
+            file_name,
+            std::fs::read(path).map_err(|err| err.to_string())?,
+        );
+    }
+    Ok(samples)
+}
@@ -0,0 +1,252 @@
+use std::path::Path;
+
+use clap::Args;
+use serde::Deserialize;
+
+use super::test::{
+    get_time_string, parse_time_offset, run_file_detection, DetectorSettings, FileDetection,
+    RunOptions,
+};
+
+#[derive(Args, Debug)]
+/// Verify the detections on the records listed in a manifest file.
+#[clap()]
+pub struct VerifyCommand {
+    #[clap()]
+    /// Manifest path, in yaml or toml format.
+    manifest_path: String,
+    #[clap(long, default_value_t = 3000)]
+    /// Milliseconds of silence appended after each record, so a detection at the end of the file can be emitted.
+    tail_ms: usize,
+    #[clap(long)]
+    /// Log the detections found on each record.
+    verbose: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+/// Records to verify, paths are relative to the manifest location.
+struct Manifest {
+    #[serde(default)]
+    /// Models loaded for every record.
+    models: Vec<String>,
+    #[serde(default)]
+    /// Detector settings used for every record.
+    detector: DetectorSettings,
+    #[serde(default = "default_tolerance_ms")]
+    /// Allowed distance between an expected offset and the detection.
+    tolerance_ms: usize,
+    files: Vec<ManifestFile>,
+}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManifestFile {
+    path: String,
+    /// Replaces the manifest models for this record.
+    models: Option<Vec<String>>,
+    /// Replaces the manifest detector settings for this record.
+    detector: Option<DetectorSettings>,
+    tolerance_ms: Option<usize>,
+    #[serde(default)]
+    /// Expected detections, records without them should not produce any detection.
+    expect: Vec<ExpectedDetection>,
+}
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ExpectedDetection {
+    name: String,
+    /// Number of detections, defaults to the number of offsets or one.
+    /// The counts of the entries with the same name add up.
+    count: Option<usize>,
+    #[serde(default)]
+    /// Approximated offsets of the detections.
+    at: Vec<TimeOffset>,
+}
+#[derive(Deserialize)]
+#[serde(untagged)]
+/// Offset expressed in seconds or in the format "[[hh:]mm:]ss[.mmm]".
+enum TimeOffset {
+    Seconds(f64),
+    Text(String),
+}
+impl TimeOffset {
+    fn as_ms(&self) -> Result<usize, String> {
+        match self {
+            TimeOffset::Seconds(seconds) if *seconds >= 0. => {
+                Ok((seconds * 1000.).round() as usize)
+            }
+            TimeOffset::Seconds(seconds) => Err(format!("Invalid time offset '{}'", seconds)),
+            TimeOffset::Text(text) => parse_time_offset(text),
+        }
+    }
+}
+fn default_tolerance_ms() -> usize {
+    500
+}
+
+pub fn verify(command: VerifyCommand) -> Result<(), String> {
+    let manifest = read_manifest(&command.manifest_path)?;
+    let base_path = Path::new(&command.manifest_path)
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let resolve_path = |path: &str| base_path.join(path).to_string_lossy().to_string();
+    println!(
+        "Verifying {} records from {}!",
+        manifest.files.len(),
+        command.manifest_path
+    );
+    let options = RunOptions {
+        tail_ms: command.tail_ms,
+        quiet: !command.verbose,
+        ..Default::default()
+    };
+    let mut failed_files = 0;
+    for file in &manifest.files {
+        let model_paths = file
+            .models
+            .as_ref()
+            .unwrap_or(&manifest.models)
+            .iter()
+            .map(|path| resolve_path(path))
+            .collect::<Vec<_>>();
+        let settings = file.detector.as_ref().unwrap_or(&manifest.detector);
+        let tolerance_ms = file.tolerance_ms.unwrap_or(manifest.tolerance_ms);
+        let differences = if model_paths.is_empty() {
+            vec!["  ! no models configured".to_string()]
+        } else {
+            match run_file_detection(&resolve_path(&file.path), &model_paths, settings, &options) {
+                Ok(detections) => get_differences(&file.expect, &detections, tolerance_ms)
+                    .unwrap_or_else(|err| vec![format!("  ! {}", err)]),
+                Err(err) => vec![format!("  ! {}", err)],
+            }
+        };
+        if differences.is_empty() {
+            println!("PASS {}", file.path);
+        } else {
+            failed_files += 1;
+            println!("FAIL {}", file.path);
+            differences
+                .iter()
+                .for_each(|difference| println!("{}", difference));
+        }
+    }
+    println!(
+        "{} of {} records passed.",
+        manifest.files.len() - failed_files,
+        manifest.files.len()
+    );
+    if failed_files != 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn read_manifest(path: &str) -> Result<Manifest, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|err| "Unable to read manifest ".to_owned() + path + ": " + &err.to_string())?;
+    match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&content).map_err(|err| err.to_string()),
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&content).map_err(|err| err.to_string()),
+        _ => Err("Unsupported manifest format, use a .toml or .yaml file".to_string()),
+    }
+}
+
+/// Compares the detections with the expected ones, returns a line per mismatch.
+fn get_differences(
+    expected_detections: &[ExpectedDetection],
+    detections: &[FileDetection],
+    tolerance_ms: usize,
+) -> Result<Vec<String>, String> {
+    let mut differences = Vec::new();
+    // offsets are matched in time order, each detection to a single offset
+    let mut expected_offsets = expected_detections
+        .iter()
+        .flat_map(|expected| {
+            expected
+                .at
+                .iter()
+                .map(|offset| Ok((offset.as_ms()?, expected.name.as_str())))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    expected_offsets.sort();
+    let mut matched = vec![false; detections.len()];
+    for (offset_ms, name) in expected_offsets {
+        let matching_detection = detections
+            .iter()
+            .enumerate()
+            .position(|(index, detection)| {
+                !matched[index]
+                    && detection.name.eq(name)
+                    && detection.start_ms.saturating_sub(tolerance_ms) <= offset_ms
+                    && offset_ms <= detection.end_ms + tolerance_ms
+            });
+        match matching_detection {
+            Some(index) => matched[index] = true,
+            None => differences.push(format!(
+                "  - {}: missing detection at {} (±{}ms)",
+                name,
+                get_time_string(offset_ms),
+                tolerance_ms
+            )),
+        }
+    }
+    let mut expected_counts: Vec<(&str, usize)> = Vec::new();
+    for expected in expected_detections {
+        let expected_count = expected.count.unwrap_or(if expected.at.is_empty() {
+            1
+        } else {
+            expected.at.len()
+        });
+        match expected_counts
+            .iter_mut()
+            .find(|(name, _)| expected.name.eq(name))
+        {
+            Some((_, count)) => *count += expected_count,
+            None => expected_counts.push((&expected.name, expected_count)),
+        }
+    }
+    for (name, expected_count) in expected_counts {
+        let count = detections
+            .iter()
+            .filter(|detection| detection.name.eq(name))
+            .count();
+        if count != expected_count {
+            differences.push(format!(
+                "  - {}: expected {} detections, got {}",
+                name, expected_count, count
+            ));
+            detections
+                .iter()
+                .enumerate()
+                .filter(|(index, detection)| !matched[*index] && detection.name.eq(name))
+                .for_each(|(_, detection)| {
+                    differences.push(format!("  + {}", get_detection_string(detection)))
+                });
+        }
+    }
+    detections
+        .iter()
+        .filter(|detection| {
+            !expected_detections
+                .iter()
+                .any(|expected| expected.name.eq(&detection.name))
+        })
+        .for_each(|detection| {
+            differences.push(format!(
+                "  + unexpected {}",
+                get_detection_string(detection)
+            ))
+        });
+    Ok(differences)
+}
+
+fn get_detection_string(detection: &FileDetection) -> String {
+    format!(
+        "{}: detection at {} - {} (score {:.3}, {})",
+        detection.name,
+        get_time_string(detection.start_ms),
+        get_time_string(detection.end_ms),
+        detection.score,
+        detection.model_path
+    )
+}
@@ -0,0 +1,73 @@
+use std::{
+    collections::HashMap,
+    f64::consts::{PI, SQRT_2},
+};
+
+use gag::Gag;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rustpotter::{
+    ModelType, TensorData, WakewordModel, WakewordModelTrain, WakewordModelTrainOptions,
+};
+
+/// Returns an untrained model for the records, with its weights initialized from the seed
+/// in the same way the library does it randomly.
+pub(crate) fn init_model(
+    model_type: &ModelType,
+    mfcc_size: u16,
+    train_samples: &HashMap<String, Vec<u8>>,
+    test_samples: &HashMap<String, Vec<u8>>,
+    seed: u64,
+) -> Result<WakewordModel, String> {
+    // the layers, labels and audio duration are taken from a training without epochs
+    let options = WakewordModelTrainOptions::new(model_type.clone(), 0., 0, 1, mfcc_size);
+    let stdout_gag = Gag::stdout().map_err(|err| err.to_string())?;
+    let result = WakewordModel::train_from_buffers(
+        options,
+        train_samples.clone(),
+        test_samples.clone(),
+        None,
+    );
+    drop(stdout_gag);
+    let mut model = result.map_err(|err| err.to_string())?;
+    // the labels order depends on the records iteration order
+    model.labels.sort();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut names = model.weights.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    for name in names {
+        let weight_name = match name.strip_suffix(".bias") {
+            Some(layer) => layer.to_string() + ".weight",
+            None => name.clone(),
+        };
+        let in_size = model.weights[&weight_name].dims[1];
+        let tensor = model.weights.get_mut(&name).expect("Missing tensor");
+        let len = tensor.dims.iter().product::<usize>();
+        let values = if name == weight_name {
+            // kaiming normal initialization for relu layers
+            let std = SQRT_2 / (in_size as f64).sqrt();
+            (0..len)
+                .map(|_| (get_normal(&mut rng) * std) as f32)
+                .collect::<Vec<_>>()
+        } else {
+            let bound = 1. / (in_size as f64).sqrt();
+            (0..len)
+                .map(|_| rng.gen_range(-bound..bound) as f32)
+                .collect::<Vec<_>>()
+        };
+        *tensor = TensorData {
+            bytes: values
+                .iter()
+                .flat_map(|value| value.to_ne_bytes())
+                .collect(),
+            dims: tensor.dims.clone(),
+            d_type: tensor.d_type.clone(),
+        };
+    }
+    Ok(model)
+}
+/// Returns a value of the standard normal distribution, using the Box-Muller transform.
+fn get_normal(rng: &mut StdRng) -> f64 {
+    let u1 = 1. - rng.gen::<f64>();
+    let u2 = rng.gen::<f64>();
+    (-2. * u1.ln()).sqrt() * (2. * PI * u2).cos()
+}